@@ -6,19 +6,27 @@ use rustretro_plugin::*;
 extern crate alloc;
 
 use alloc::boxed::Box;
-use alloc::string::ToString;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
 use alloc::vec::Vec;
 
-struct NestadiaRustretro {
+pub struct NestadiaRustretro {
     emulator: Emulator,
+    // Kept to power cycle the console
+    rom: Vec<u8>,
 }
 
 #[rustretro_plugin]
 impl RustretroPlugin for NestadiaRustretro {
-    fn create_core(rom: &[u8]) -> Box<Self> {
-        Box::new(Self {
-            emulator: Emulator::new(rom, None).unwrap(),
-        })
+    fn create_core(rom: &[u8], save_data: Option<&[u8]>) -> Result<Box<Self>, String> {
+        let emulator = Emulator::new(rom, save_data)
+            .map_err(|e| format!("Could not load the ROM: {:?}", e))?;
+
+        Ok(Box::new(Self {
+            emulator,
+            rom: rom.to_vec(),
+        }))
     }
 
     fn get_metadata(&self) -> Box<Metadata> {
@@ -29,11 +37,29 @@ impl RustretroPlugin for NestadiaRustretro {
 
             pixel_format: PixelFormat::RGBA,
             frames_per_seconds: 59.94f32,
+
+            // The APU isn't emulated yet
+            audio_sample_rate: 0,
+            audio_channels: 0,
+
+            // Two standard controllers. Nestadia doesn't emulate the Zapper, so a pointer can't
+            // be plugged in the second port yet: it only exposes the joypad shift registers
+            // through `set_controller1`/`set_controller2`, while the Zapper's trigger and light
+            // sensor are read from other bits of $4017
+            ports: vec![joypad(); 2],
+
+            memory_regions: Vec::new(),
         })
     }
 
-    fn controller_input(&mut self, input: ControllerInput) {
-        self.emulator.set_controller1(input.bits())
+    fn controller_input(&mut self, port: u32, input: &DeviceInput) {
+        // The buttons are laid out like `ControllerInput`
+        let buttons = input.buttons as u8;
+
+        match port {
+            0 => self.emulator.set_controller1(buttons),
+            _ => self.emulator.set_controller2(buttons),
+        }
     }
 
     fn clock_until_frame(&mut self) -> Vec<u8> {
@@ -51,4 +77,43 @@ impl RustretroPlugin for NestadiaRustretro {
 
         buffer.to_vec()
     }
+
+    fn reset(&mut self, kind: ResetKind) {
+        match kind {
+            ResetKind::Soft => self.emulator.reset(),
+            ResetKind::Hard => {
+                // The ROM was already parsed once, so this can't fail
+                let save_data = self.emulator.get_save_data().map(|data| data.to_vec());
+                self.emulator = Emulator::new(&self.rom, save_data.as_deref())
+                    .expect("the ROM should still be valid");
+            }
+        }
+    }
+
+    fn save_data(&self) -> Option<Vec<u8>> {
+        self.emulator.get_save_data().map(|data| data.to_vec())
+    }
+}
+
+/// The standard controller, with the buttons laid out like `ControllerInput`
+fn joypad() -> Device {
+    let button = |input: ControllerInput, label, role| {
+        Control::new(input.bits().trailing_zeros(), label, Some(role))
+    };
+
+    Device {
+        device_type: DeviceType::Joypad,
+        buttons: vec![
+            button(ControllerInput::A, "A", ControlRole::FaceEast),
+            button(ControllerInput::B, "B", ControlRole::FaceSouth),
+            button(ControllerInput::SELECT, "Select", ControlRole::Select),
+            button(ControllerInput::START, "Start", ControlRole::Start),
+            button(ControllerInput::UP, "Up", ControlRole::Up),
+            button(ControllerInput::DOWN, "Down", ControlRole::Down),
+            button(ControllerInput::LEFT, "Left", ControlRole::Left),
+            button(ControllerInput::RIGHT, "Right", ControlRole::Right),
+        ],
+        axes: Vec::new(),
+        triggers: Vec::new(),
+    }
 }
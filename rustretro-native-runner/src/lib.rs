@@ -0,0 +1,278 @@
+//! Drives a core linked into the frontend, without any sandbox. This lets a native debugger or
+//! profiler see the core's code, at the cost of trusting it completely.
+
+use std::fmt;
+
+use rustretro_plugin::{
+    Capabilities, DeviceInput, DeviceType, KeyboardEvent, Metadata, ResetKind, RustretroPlugin,
+};
+use rustretro_runner_common::{is_valid_memory_range, CoreRuntime, SaveDataTracker};
+
+#[derive(Debug)]
+pub enum RunnerError {
+    /// The core refused to start, with its own message
+    CreateCore(String),
+    /// The core doesn't implement this optional feature
+    Unsupported(Capabilities),
+    /// The range isn't inside one of the memory regions the core declared
+    InvalidMemoryRange {
+        region: u32,
+        offset: u32,
+        length: usize,
+    },
+}
+
+impl fmt::Display for RunnerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CreateCore(e) => write!(f, "the core could not be created: {}", e),
+            Self::Unsupported(capability) => {
+                write!(f, "the core doesn't support {:?}", capability)
+            }
+            Self::InvalidMemoryRange {
+                region,
+                offset,
+                length,
+            } => write!(
+                f,
+                "the core didn't declare {} bytes at {:#x} in its memory region {}",
+                length, offset, region
+            ),
+        }
+    }
+}
+
+impl std::error::Error for RunnerError {}
+
+pub struct Runner<P: RustretroPlugin> {
+    core: Box<P>,
+    metadata: Metadata,
+    rom: Vec<u8>,
+
+    save_data: SaveDataTracker,
+}
+
+impl<P: RustretroPlugin> Drop for Runner<P> {
+    fn drop(&mut self) {
+        // Don't lose the save data that changed since the last flush
+        self.flush_save_data();
+    }
+}
+
+impl<P: RustretroPlugin> Runner<P> {
+    /// Create the emulator. Fails with the core's message if it rejects the ROM.
+    pub fn new(rom: &[u8], save_data: Option<&[u8]>) -> Result<Self, RunnerError> {
+        let core = P::create_core(rom, save_data).map_err(RunnerError::CreateCore)?;
+        let metadata = *core.get_metadata();
+
+        Ok(Self {
+            core,
+            metadata,
+            rom: rom.to_vec(),
+
+            save_data: SaveDataTracker::new(save_data),
+        })
+    }
+
+    /// Recreate the emulator, starting from the last save data fetched
+    pub fn reload(&mut self) -> Result<(), RunnerError> {
+        self.core =
+            P::create_core(&self.rom, self.save_data.last()).map_err(RunnerError::CreateCore)?;
+
+        Ok(())
+    }
+
+    /// Direct access to the core, to inspect it while debugging
+    pub fn core(&mut self) -> &mut P {
+        &mut self.core
+    }
+
+    /// Input on a port the core doesn't have is ignored, like an unplugged controller
+    pub fn controller_input(&mut self, port: u32, input: &DeviceInput) {
+        if (port as usize) < self.metadata.ports.len() {
+            self.core.controller_input(port, input)
+        }
+    }
+
+    pub fn clock_until_frame(&mut self) -> Vec<u8> {
+        self.core.clock_until_frame()
+    }
+
+    /// Events on a port without a keyboard are ignored
+    pub fn keyboard_input(&mut self, port: u32, event: KeyboardEvent) {
+        let device = self.metadata.ports.get(port as usize);
+
+        if P::CAPABILITIES.contains(Capabilities::KEYBOARD)
+            && device.map(|device| device.device_type) == Some(DeviceType::Keyboard)
+        {
+            self.core.keyboard_input(port, event)
+        }
+    }
+
+    /// Reset the console, keeping the save data
+    pub fn reset(&mut self, kind: ResetKind) -> Result<(), RunnerError> {
+        if !P::CAPABILITIES.contains(Capabilities::RESET) {
+            return Err(RunnerError::Unsupported(Capabilities::RESET));
+        }
+
+        self.core.reset(kind);
+        Ok(())
+    }
+
+    /// Fetch the interleaved PCM samples generated during the last frame
+    pub fn audio_samples(&mut self) -> Vec<i16> {
+        self.core.audio_samples()
+    }
+
+    /// Fetch the battery-backed RAM, only if it changed since the last time it was fetched
+    pub fn dirty_save_data(&mut self) -> Option<Vec<u8>> {
+        self.save_data.update(self.core.save_data())
+    }
+
+    /// Set the function that receives the save data when it is flushed
+    pub fn on_save_data(&mut self, handler: impl FnMut(&[u8]) + Send + 'static) {
+        self.save_data.set_handler(handler);
+    }
+
+    /// Send the save data to the handler if it changed since the last flush.
+    /// This is also done when the runner is dropped.
+    pub fn flush_save_data(&mut self) {
+        if self.save_data.has_handler() {
+            self.save_data.flush(self.core.save_data());
+        }
+    }
+
+    /// Serialize the emulator state
+    pub fn save_state(&mut self) -> Result<Vec<u8>, RunnerError> {
+        if !P::CAPABILITIES.contains(Capabilities::SAVE_STATE) {
+            return Err(RunnerError::Unsupported(Capabilities::SAVE_STATE));
+        }
+
+        Ok(self.core.save_state())
+    }
+
+    /// Restore a state previously returned by `save_state`
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), RunnerError> {
+        if !P::CAPABILITIES.contains(Capabilities::SAVE_STATE) {
+            return Err(RunnerError::Unsupported(Capabilities::SAVE_STATE));
+        }
+
+        self.core.load_state(state);
+        Ok(())
+    }
+
+    /// Copy `length` bytes of a region of `Metadata::memory_regions`, starting at `offset`
+    pub fn read_memory(
+        &mut self,
+        region: u32,
+        offset: u32,
+        length: u32,
+    ) -> Result<Vec<u8>, RunnerError> {
+        self.check_memory_range(region, offset, length as usize)?;
+
+        Ok(self.core.read_memory(region, offset, length))
+    }
+
+    /// Overwrite a region of `Metadata::memory_regions`, starting at `offset`
+    pub fn write_memory(
+        &mut self,
+        region: u32,
+        offset: u32,
+        data: &[u8],
+    ) -> Result<(), RunnerError> {
+        self.check_memory_range(region, offset, data.len())?;
+
+        self.core.write_memory(region, offset, data);
+        Ok(())
+    }
+
+    /// The core is promised to only get ranges inside the regions it declared
+    fn check_memory_range(
+        &self,
+        region: u32,
+        offset: u32,
+        length: usize,
+    ) -> Result<(), RunnerError> {
+        if !P::CAPABILITIES.contains(Capabilities::MEMORY_ACCESS) {
+            return Err(RunnerError::Unsupported(Capabilities::MEMORY_ACCESS));
+        }
+
+        if !is_valid_memory_range(&self.metadata, region, offset, length) {
+            return Err(RunnerError::InvalidMemoryRange {
+                region,
+                offset,
+                length,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn get_metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+}
+
+impl<P: RustretroPlugin> CoreRuntime for Runner<P> {
+    type Error = RunnerError;
+
+    fn get_metadata(&self) -> &Metadata {
+        Runner::get_metadata(self)
+    }
+
+    fn controller_input(&mut self, port: u32, input: &DeviceInput) -> Result<(), RunnerError> {
+        Runner::controller_input(self, port, input);
+        Ok(())
+    }
+
+    fn clock_until_frame(&mut self) -> Result<Vec<u8>, RunnerError> {
+        Ok(Runner::clock_until_frame(self))
+    }
+
+    fn keyboard_input(&mut self, port: u32, event: KeyboardEvent) -> Result<(), RunnerError> {
+        Runner::keyboard_input(self, port, event);
+        Ok(())
+    }
+
+    fn reset(&mut self, kind: ResetKind) -> Result<(), RunnerError> {
+        Runner::reset(self, kind)
+    }
+
+    fn audio_samples(&mut self) -> Result<Vec<i16>, RunnerError> {
+        Ok(Runner::audio_samples(self))
+    }
+
+    fn flush_save_data(&mut self) -> Result<(), RunnerError> {
+        Runner::flush_save_data(self);
+        Ok(())
+    }
+
+    fn save_state(&mut self) -> Result<Vec<u8>, RunnerError> {
+        Runner::save_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), RunnerError> {
+        Runner::load_state(self, state)
+    }
+
+    fn read_memory(
+        &mut self,
+        region: u32,
+        offset: u32,
+        length: u32,
+    ) -> Result<Vec<u8>, RunnerError> {
+        Runner::read_memory(self, region, offset, length)
+    }
+
+    fn write_memory(&mut self, region: u32, offset: u32, data: &[u8]) -> Result<(), RunnerError> {
+        Runner::write_memory(self, region, offset, data)
+    }
+
+    fn reload(&mut self) -> Result<(), RunnerError> {
+        Runner::reload(self)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        P::CAPABILITIES
+    }
+}
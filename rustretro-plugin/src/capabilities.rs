@@ -0,0 +1,15 @@
+use bitflags::bitflags;
+
+bitflags! {
+    /// Optional features of a core. `#[rustretro_plugin]` sets them from the methods of the impl
+    /// block, and the runners read them during the ABI handshake.
+    #[derive(Default)]
+    pub struct Capabilities: u32 {
+        const AUDIO = 0x01;
+        const SAVE_DATA = 0x02;
+        const SAVE_STATE = 0x04;
+        const KEYBOARD = 0x08;
+        const RESET = 0x10;
+        const MEMORY_ACCESS = 0x20;
+    }
+}
@@ -1,6 +1,7 @@
 use bitflags::bitflags;
 
 bitflags! {
+    /// The buttons of a NES controller, as sent in `DeviceInput::buttons`
     #[derive(Default)]
     pub struct ControllerInput: u8 {
         const A = 0x80;
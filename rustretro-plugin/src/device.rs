@@ -0,0 +1,112 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::ControllerInput;
+
+/// The family of a device, so frontends know how to map their own inputs to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeviceType {
+    /// Only digital buttons, like the NES controller
+    Joypad,
+    /// Buttons with analog sticks and triggers
+    Analog,
+    /// A light gun or a mouse aimed at the screen. Button 0 is the trigger
+    Pointer,
+    /// A computer keyboard, which gets `KeyboardEvent`s instead of `DeviceInput`
+    Keyboard,
+}
+
+/// A device a core accepts in one of its ports, with the layout of its controls so frontends can
+/// bind them without knowing the system
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Device {
+    pub device_type: DeviceType,
+    /// Digital buttons, up to 64
+    pub buttons: Vec<Control>,
+    pub axes: Vec<Control>,
+    pub triggers: Vec<Control>,
+}
+
+/// A button, axis or trigger of a device
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Control {
+    /// The bit of `DeviceInput::buttons`, or the index in `axes` or `triggers`
+    pub id: u32,
+    /// Human readable name, like "Start"
+    pub label: String,
+    /// Where the control is on a modern gamepad, to pick a default binding
+    pub role: Option<ControlRole>,
+}
+
+impl Control {
+    pub fn new(id: u32, label: &str, role: Option<ControlRole>) -> Self {
+        Self {
+            id,
+            label: label.into(),
+            role,
+        }
+    }
+}
+
+/// The standard position of a control. Face buttons are named by position, since the labels
+/// differ between systems
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ControlRole {
+    Up,
+    Down,
+    Left,
+    Right,
+    Start,
+    Select,
+    FaceSouth,
+    FaceEast,
+    FaceWest,
+    FaceNorth,
+    ShoulderLeft,
+    ShoulderRight,
+    LeftStickX,
+    LeftStickY,
+    RightStickX,
+    RightStickY,
+    TriggerLeft,
+    TriggerRight,
+}
+
+/// The state of a device, sent every time it changes
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceInput {
+    /// Bit `n` is set while button `n` is held
+    pub buttons: u64,
+    /// Stick axes, 0 being the center
+    pub axes: Vec<i16>,
+    /// Analog triggers, 0 being released
+    pub triggers: Vec<u16>,
+    /// Where a pointer aims, `None` when it is outside of the screen
+    pub pointer: Option<PointerPosition>,
+}
+
+/// A position on the emulated screen, in pixels from the top left corner
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PointerPosition {
+    pub x: u32,
+    pub y: u32,
+}
+
+impl From<ControllerInput> for DeviceInput {
+    fn from(input: ControllerInput) -> Self {
+        Self {
+            buttons: input.bits() as u64,
+            ..Default::default()
+        }
+    }
+}
+
+/// A key of a keyboard device was pressed or released
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyboardEvent {
+    /// USB HID usage ID of the key, from the keyboard page. It is the position of the key on a
+    /// US layout, whatever the layout of the host
+    pub keycode: u16,
+    pub pressed: bool,
+}
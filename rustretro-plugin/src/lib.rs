@@ -3,21 +3,72 @@
 extern crate alloc;
 
 use alloc::boxed::Box;
+use alloc::string::String;
 use alloc::vec::Vec;
 
+mod capabilities;
 mod controller_input;
+mod device;
+mod memory_region;
 mod metadata;
 mod pixel_format;
+mod reset_kind;
 
+pub use capabilities::*;
 pub use controller_input::*;
+pub use device::*;
+pub use memory_region::*;
 pub use metadata::*;
 pub use pixel_format::*;
+pub use reset_kind::*;
 pub use rustretro_procmacro::rustretro_plugin;
 pub use serde_json;
 
+/// Version of the contract between the exports generated by `#[rustretro_plugin]` and the runners
+pub const ABI_VERSION: u32 = 9;
+
+/// Custom section where `#[rustretro_plugin]` writes `ABI_VERSION` as little endian bytes, so it
+/// can be read without instantiating the core
+pub const ABI_VERSION_SECTION: &str = "rustretro_abi_version";
+
+/// The methods with a default implementation are optional capabilities. `#[rustretro_plugin]`
+/// sets `CAPABILITIES` from the ones the impl block overrides.
 pub trait RustretroPlugin {
-    fn create_core(rom: &[u8]) -> Box<Self>;
+    const CAPABILITIES: Capabilities = Capabilities::empty();
+
+    /// Returns a human readable message if the core can't be created, for example with a bad ROM
+    fn create_core(rom: &[u8], save_data: Option<&[u8]>) -> Result<Box<Self>, String>;
     fn get_metadata(&self) -> Box<Metadata>;
-    fn controller_input(&mut self, input: ControllerInput);
+    /// `port` is always an index in `Metadata::ports`
+    fn controller_input(&mut self, port: u32, input: &DeviceInput);
     fn clock_until_frame(&mut self) -> Vec<u8>;
+    fn reset(&mut self, _kind: ResetKind) {}
+
+    /// Only called on the ports with a `DeviceType::Keyboard`
+    fn keyboard_input(&mut self, _port: u32, _event: KeyboardEvent) {}
+
+    /// Interleaved PCM samples generated during the last `clock_until_frame` call
+    fn audio_samples(&mut self) -> Vec<i16> {
+        Vec::new()
+    }
+
+    /// Current content of the battery-backed RAM, if the game has any
+    fn save_data(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Serialize the whole emulator state
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    /// Restore a state previously returned by `save_state`
+    fn load_state(&mut self, _state: &[u8]) {}
+
+    /// Copy `length` bytes of a region of `Metadata::memory_regions`, starting at `offset`.
+    /// `region` is always an index in it, and the range is always inside the region.
+    fn read_memory(&self, _region: u32, _offset: u32, _length: u32) -> Vec<u8> {
+        Vec::new()
+    }
+    /// Overwrite a region, with the same guarantees as `read_memory`
+    fn write_memory(&mut self, _region: u32, _offset: u32, _data: &[u8]) {}
 }
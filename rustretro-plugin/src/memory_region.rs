@@ -0,0 +1,26 @@
+use alloc::string::String;
+use serde::{Deserialize, Serialize};
+
+/// A block of the emulated memory that the host can read and write, like the work RAM, for
+/// debuggers, cheats or achievements
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryRegion {
+    /// Human readable name, like "WRAM"
+    pub name: String,
+    /// In bytes
+    pub size: u32,
+}
+
+impl MemoryRegion {
+    pub fn new(name: &str, size: u32) -> Self {
+        Self {
+            name: name.into(),
+            size,
+        }
+    }
+
+    /// If `length` bytes starting at `offset` are inside the region
+    pub fn contains(&self, offset: u32, length: usize) -> bool {
+        matches!((offset as usize).checked_add(length), Some(end) if end <= self.size as usize)
+    }
+}
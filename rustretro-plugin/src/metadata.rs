@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 
-use crate::PixelFormat;
+use alloc::vec::Vec;
+
+use crate::{Device, MemoryRegion, PixelFormat};
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Metadata {
@@ -10,4 +12,14 @@ pub struct Metadata {
 
     pub pixel_format: PixelFormat,
     pub frames_per_seconds: f32,
+
+    /// Set to 0 if the core doesn't output audio
+    pub audio_sample_rate: u32,
+    pub audio_channels: u32,
+
+    /// The device expected in each controller port of the console
+    pub ports: Vec<Device>,
+
+    /// The memory the host can access when the core has `Capabilities::MEMORY_ACCESS`
+    pub memory_regions: Vec<MemoryRegion>,
 }
@@ -10,3 +10,11 @@ impl Default for PixelFormat {
         Self::RGBA
     }
 }
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(&self) -> u32 {
+        match self {
+            Self::RGBA => 4,
+        }
+    }
+}
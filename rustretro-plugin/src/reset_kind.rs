@@ -0,0 +1,26 @@
+/// How the console is reset
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ResetKind {
+    /// Like pressing the reset button: the CPU restarts, but the RAM keeps its content
+    Soft,
+    /// Like power cycling the console. Only the battery-backed RAM is kept
+    Hard,
+}
+
+impl ResetKind {
+    /// The value passed to `__rustretro_plugin_reset`
+    pub fn bits(self) -> u32 {
+        match self {
+            Self::Soft => 0,
+            Self::Hard => 1,
+        }
+    }
+
+    /// Unknown values come from a newer host, and are treated as a soft reset
+    pub fn from_bits_truncate(bits: u32) -> Self {
+        match bits {
+            1 => Self::Hard,
+            _ => Self::Soft,
+        }
+    }
+}
@@ -6,7 +6,7 @@ pub fn rustretro_plugin(_attr: TokenStream, tokens: TokenStream) -> TokenStream
     let tokens = proc_macro2::TokenStream::from(tokens);
     let item: syn::Item = syn::parse2(tokens).expect("Unable to parse");
 
-    let trait_impl = match item {
+    let mut trait_impl = match item {
         syn::Item::Impl(x) => x,
         _ => panic!("The attribute should be on an impl block"),
     };
@@ -20,16 +20,97 @@ pub fn rustretro_plugin(_attr: TokenStream, tokens: TokenStream) -> TokenStream
         _ => panic!("Implement is not on a struct!"),
     };
 
+    // Advertise the optional methods the core implements
+    let capabilities: Vec<syn::Ident> = trait_impl
+        .items
+        .iter()
+        .filter_map(|item| match item {
+            syn::ImplItem::Method(method) => match method.sig.ident.to_string().as_str() {
+                "audio_samples" => Some("AUDIO"),
+                "save_data" => Some("SAVE_DATA"),
+                "save_state" => Some("SAVE_STATE"),
+                "keyboard_input" => Some("KEYBOARD"),
+                "reset" => Some("RESET"),
+                "read_memory" => Some("MEMORY_ACCESS"),
+                _ => None,
+            },
+            _ => None,
+        })
+        .map(|name| syn::Ident::new(name, proc_macro2::Span::call_site()))
+        .collect();
+
+    // Unless the core sets them itself, so native runners can read them too
+    let has_capabilities = trait_impl
+        .items
+        .iter()
+        .any(|item| matches!(item, syn::ImplItem::Const(x) if x.ident == "CAPABILITIES"));
+
+    if !has_capabilities {
+        trait_impl.items.push(syn::parse_quote! {
+            const CAPABILITIES: ::rustretro_plugin::Capabilities =
+                ::rustretro_plugin::Capabilities::from_bits_truncate(
+                    0 #(| ::rustretro_plugin::Capabilities::#capabilities.bits())*
+                );
+        });
+    }
+
     let expanded = quote::quote! {
         #trait_impl
 
+        #[no_mangle]
+        pub unsafe fn __rustretro_plugin_abi_version() -> u32 {
+            ::rustretro_plugin::ABI_VERSION
+        }
+
+        // Must match `ABI_VERSION_SECTION`, attributes can't take a constant
+        #[cfg_attr(target_arch = "wasm32", link_section = "rustretro_abi_version")]
+        #[used]
+        static __RUSTRETRO_PLUGIN_ABI_VERSION: [u8; 4] = ::rustretro_plugin::ABI_VERSION.to_le_bytes();
+
+        #[no_mangle]
+        pub unsafe fn __rustretro_plugin_capabilities() -> u32 {
+            <#struct_ident as ::rustretro_plugin::RustretroPlugin>::CAPABILITIES.bits()
+        }
+
         extern crate alloc as _rustretro_plugin_alloc;
         #[no_mangle]
-        pub unsafe fn __rustretro_plugin_create_core(ptr: u32, length: u32) -> u32 {
+        pub unsafe fn __rustretro_plugin_create_core(
+            ptr: u32,
+            length: u32,
+            save_ptr: u32,
+            save_length: u32,
+        ) -> u32 {
             let rom = ::core::slice::from_raw_parts(ptr as *const u8, length as usize);
-            let emulator = #struct_ident::create_core(rom);
 
-            ::_rustretro_plugin_alloc::boxed::Box::into_raw(emulator) as u32
+            // An empty buffer means there is no save data
+            let save_data = if save_length == 0 {
+                None
+            } else {
+                Some(::core::slice::from_raw_parts(save_ptr as *const u8, save_length as usize))
+            };
+
+            match #struct_ident::create_core(rom, save_data) {
+                Ok(emulator) => ::_rustretro_plugin_alloc::boxed::Box::into_raw(emulator) as u32,
+                Err(e) => {
+                    // The host fetches the message with `__rustretro_plugin_take_error`
+                    *::core::ptr::addr_of_mut!(__RUSTRETRO_PLUGIN_ERROR) = Some(e);
+                    0
+                }
+            }
+        }
+
+        static mut __RUSTRETRO_PLUGIN_ERROR: Option<::_rustretro_plugin_alloc::string::String> = None;
+
+        #[no_mangle]
+        pub unsafe fn __rustretro_plugin_take_error() -> u64 {
+            let error = ::core::mem::take(&mut *::core::ptr::addr_of_mut!(__RUSTRETRO_PLUGIN_ERROR));
+
+            let data = error.unwrap_or_default().into_bytes();
+            let length = data.len() as u64;
+
+            let ptr = ::_rustretro_plugin_alloc::boxed::Box::into_raw(data.into_boxed_slice()) as *mut u8 as u64;
+
+            ptr | (length << 32)
         }
 
         #[no_mangle]
@@ -47,11 +128,30 @@ pub fn rustretro_plugin(_attr: TokenStream, tokens: TokenStream) -> TokenStream
         }
 
         #[no_mangle]
-        pub unsafe fn __rustretro_plugin_controller_input(ptr: u32, input: u32) {
+        pub unsafe fn __rustretro_plugin_controller_input(
+            ptr: u32,
+            port: u32,
+            input_ptr: u32,
+            length: u32,
+        ) {
+            let emulator = &mut *(ptr as *mut #struct_ident);
+            let input = ::core::slice::from_raw_parts(input_ptr as *const u8, length as usize);
+
+            // The host serialized it, so it can't be malformed unless the ABI changed
+            if let Ok(input) = ::rustretro_plugin::serde_json::from_slice(input) {
+                emulator.controller_input(port, &input);
+            }
+        }
+
+        #[no_mangle]
+        pub unsafe fn __rustretro_plugin_keyboard_input(ptr: u32, port: u32, keycode: u32, pressed: u32) {
             let emulator = &mut *(ptr as *mut #struct_ident);
 
-            let input = ::rustretro_plugin::ControllerInput::from_bits_truncate(input as u8);
-            emulator.controller_input(input);
+            let event = ::rustretro_plugin::KeyboardEvent {
+                keycode: keycode as u16,
+                pressed: pressed != 0,
+            };
+            emulator.keyboard_input(port, event);
         }
 
         #[no_mangle]
@@ -66,6 +166,92 @@ pub fn rustretro_plugin(_attr: TokenStream, tokens: TokenStream) -> TokenStream
             ptr | (length << 32)
         }
 
+        #[no_mangle]
+        pub unsafe fn __rustretro_plugin_reset(ptr: u32, kind: u32) {
+            let emulator = &mut *(ptr as *mut #struct_ident);
+
+            let kind = ::rustretro_plugin::ResetKind::from_bits_truncate(kind);
+            emulator.reset(kind);
+        }
+
+        #[no_mangle]
+        pub unsafe fn __rustretro_plugin_audio_samples(ptr: u32) -> u64 {
+            let emulator = &mut *(ptr as *mut #struct_ident);
+
+            // Samples are sent as little endian bytes so the buffer can be freed with `free_vec`
+            let samples: ::_rustretro_plugin_alloc::vec::Vec<u8> = emulator
+                .audio_samples()
+                .iter()
+                .flat_map(|s| s.to_le_bytes())
+                .collect();
+            let length = samples.len() as u64;
+
+            let ptr = ::_rustretro_plugin_alloc::boxed::Box::into_raw(samples.into_boxed_slice()) as *mut u8
+                as u64;
+
+            ptr | (length << 32)
+        }
+
+        #[no_mangle]
+        pub unsafe fn __rustretro_plugin_save_data(ptr: u32) -> u64 {
+            let emulator = &mut *(ptr as *mut #struct_ident);
+
+            // No save data is sent as an empty buffer
+            let data = emulator.save_data().unwrap_or_default();
+            let length = data.len() as u64;
+
+            let ptr = ::_rustretro_plugin_alloc::boxed::Box::into_raw(data.into_boxed_slice()) as *mut u8
+                as u64;
+
+            ptr | (length << 32)
+        }
+
+        #[no_mangle]
+        pub unsafe fn __rustretro_plugin_save_state(ptr: u32) -> u64 {
+            let emulator = &mut *(ptr as *mut #struct_ident);
+            let state = emulator.save_state();
+            let length = state.len() as u64;
+
+            let ptr = ::_rustretro_plugin_alloc::boxed::Box::into_raw(state.into_boxed_slice()) as *mut u8
+                as u64;
+
+            ptr | (length << 32)
+        }
+
+        #[no_mangle]
+        pub unsafe fn __rustretro_plugin_load_state(ptr: u32, state_ptr: u32, length: u32) {
+            let emulator = &mut *(ptr as *mut #struct_ident);
+            let state = ::core::slice::from_raw_parts(state_ptr as *const u8, length as usize);
+
+            emulator.load_state(state);
+        }
+
+        #[no_mangle]
+        pub unsafe fn __rustretro_plugin_read_memory(ptr: u32, region: u32, offset: u32, length: u32) -> u64 {
+            let emulator = &mut *(ptr as *mut #struct_ident);
+            let data = emulator.read_memory(region, offset, length);
+            let length = data.len() as u64;
+
+            let ptr = ::_rustretro_plugin_alloc::boxed::Box::into_raw(data.into_boxed_slice()) as *mut u8
+                as u64;
+
+            ptr | (length << 32)
+        }
+
+        #[no_mangle]
+        pub unsafe fn __rustretro_plugin_write_memory(
+            ptr: u32,
+            region: u32,
+            offset: u32,
+            data_ptr: u32,
+            length: u32,
+        ) {
+            let emulator = &mut *(ptr as *mut #struct_ident);
+            let data = ::core::slice::from_raw_parts(data_ptr as *const u8, length as usize);
+
+            emulator.write_memory(region, offset, data);
+        }
+
         #[no_mangle]
         pub unsafe fn __rustretro_plugin_alloc_vec(length: u32) -> u32 {
             Box::into_raw(::_rustretro_plugin_alloc::vec![0u8; length as usize].into_boxed_slice()) as *mut u8 as u32
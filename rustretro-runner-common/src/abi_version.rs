@@ -0,0 +1,118 @@
+use rustretro_plugin::ABI_VERSION_SECTION;
+
+/// Read the version of the ABI a core was built for from its custom section, without compiling it.
+/// Returns `None` if the core doesn't say.
+pub fn read_abi_version(core: &[u8]) -> Option<u32> {
+    custom_section(core, ABI_VERSION_SECTION)
+        .and_then(|data| data.try_into().ok())
+        .map(u32::from_le_bytes)
+}
+
+/// Find a custom section in the WebAssembly bytes of a module. The bytes come from the core, so
+/// every length is checked against what is left.
+fn custom_section<'a>(module: &'a [u8], name: &str) -> Option<&'a [u8]> {
+    // Skip the magic number and the version
+    let mut rest = module.get(8..)?;
+
+    while let Some((&id, bytes)) = rest.split_first() {
+        let (size, offset) = read_leb128(bytes)?;
+        let end = offset.checked_add(size as usize)?;
+        let payload = bytes.get(offset..end)?;
+        rest = &bytes[end..];
+
+        // Custom sections have the ID 0, and start with their name
+        if id == 0 {
+            let (name_length, offset) = read_leb128(payload)?;
+            let name_end = offset.checked_add(name_length as usize)?;
+
+            if payload.get(offset..name_end)? == name.as_bytes() {
+                return Some(&payload[name_end..]);
+            }
+        }
+    }
+
+    None
+}
+
+/// Decode an unsigned LEB128 integer, and return it with the number of bytes it used
+fn read_leb128(bytes: &[u8]) -> Option<(u32, usize)> {
+    let mut value = 0u32;
+
+    for (i, byte) in bytes.iter().take(5).enumerate() {
+        value |= ((byte & 0x7F) as u32) << (7 * i);
+
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &[u8] = b"\0asm\x01\0\0\0";
+
+    /// The bytes of a custom section, without the module header
+    fn custom(name: &str, payload: &[u8]) -> Vec<u8> {
+        let mut content = vec![name.len() as u8];
+        content.extend_from_slice(name.as_bytes());
+        content.extend_from_slice(payload);
+
+        let mut section = vec![0, content.len() as u8];
+        section.extend_from_slice(&content);
+        section
+    }
+
+    #[test]
+    fn valid_section() {
+        // An empty type section and another custom section come first
+        let core = [
+            HEADER,
+            &[1, 1, 0],
+            &custom("name", &[1, 2, 3]),
+            &custom(ABI_VERSION_SECTION, &8u32.to_le_bytes()),
+        ]
+        .concat();
+
+        assert_eq!(read_abi_version(&core), Some(8));
+    }
+
+    #[test]
+    fn truncated_section() {
+        let mut core = [HEADER, &custom(ABI_VERSION_SECTION, &8u32.to_le_bytes())].concat();
+        core.pop();
+
+        assert_eq!(read_abi_version(&core), None);
+
+        // A version that doesn't take 4 bytes
+        let core = [HEADER, &custom(ABI_VERSION_SECTION, &[8, 0, 0])].concat();
+        assert_eq!(read_abi_version(&core), None);
+    }
+
+    #[test]
+    fn oversized_length() {
+        // A section that claims to be 4 GiB long
+        let core = [HEADER, &[0, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F, 0, 0, 0]].concat();
+        assert_eq!(read_abi_version(&core), None);
+
+        // A section name that claims to be 4 GiB long
+        let core = [HEADER, &[0, 5, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F]].concat();
+        assert_eq!(read_abi_version(&core), None);
+
+        // A length that never ends
+        let core = [HEADER, &[0, 0x80, 0x80, 0x80, 0x80, 0x80, 0]].concat();
+        assert_eq!(read_abi_version(&core), None);
+    }
+
+    #[test]
+    fn missing_section() {
+        let core = [HEADER, &[1, 1, 0], &custom("name", &8u32.to_le_bytes())].concat();
+        assert_eq!(read_abi_version(&core), None);
+
+        assert_eq!(read_abi_version(HEADER), None);
+        assert_eq!(read_abi_version(&[]), None);
+    }
+}
@@ -0,0 +1,47 @@
+use std::fmt::Display;
+
+use rustretro_plugin::{Capabilities, DeviceInput, KeyboardEvent, Metadata, ResetKind};
+
+/// The host side of a core: anything that can run one, whatever the backend.
+/// Frontends drive the emulation through it.
+pub trait CoreRuntime {
+    type Error: Display;
+
+    fn get_metadata(&self) -> &Metadata;
+    /// The optional features of the core. Without them, every backend returns no audio and no save
+    /// data, ignores keyboard events, and fails `reset`, `save_state`, `load_state`, `read_memory`
+    /// and `write_memory` with an error.
+    fn capabilities(&self) -> Capabilities;
+
+    /// Input on a port the core doesn't have is ignored
+    fn controller_input(&mut self, port: u32, input: &DeviceInput) -> Result<(), Self::Error>;
+    /// Events on a port without a keyboard are ignored
+    fn keyboard_input(&mut self, port: u32, event: KeyboardEvent) -> Result<(), Self::Error>;
+    fn clock_until_frame(&mut self) -> Result<Vec<u8>, Self::Error>;
+    fn reset(&mut self, kind: ResetKind) -> Result<(), Self::Error>;
+    /// Interleaved PCM samples generated during the last frame
+    fn audio_samples(&mut self) -> Result<Vec<i16>, Self::Error>;
+
+    /// Hand the save data to the backend's storage if it changed
+    fn flush_save_data(&mut self) -> Result<(), Self::Error>;
+    fn save_state(&mut self) -> Result<Vec<u8>, Self::Error>;
+    fn load_state(&mut self, state: &[u8]) -> Result<(), Self::Error>;
+
+    /// Copy `length` bytes of a region of `Metadata::memory_regions`, starting at `offset`. A range
+    /// outside of the region is an error.
+    fn read_memory(
+        &mut self,
+        region: u32,
+        offset: u32,
+        length: u32,
+    ) -> Result<Vec<u8>, Self::Error>;
+    fn write_memory(&mut self, region: u32, offset: u32, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Recreate the emulator from the ROM and the last save data
+    fn reload(&mut self) -> Result<(), Self::Error>;
+
+    /// If the core can keep running after a `reload` following this error
+    fn is_recoverable(&self, _error: &Self::Error) -> bool {
+        false
+    }
+}
@@ -0,0 +1,9 @@
+/// Fuel given to each call into the core, when a runner meters it with fuel instead of wall-clock
+/// time. A call that runs out of fuel is aborted.
+#[derive(Clone, Copy, Debug)]
+pub struct FuelBudget {
+    pub create_core: u64,
+    pub clock_until_frame: u64,
+    /// Every other call, like input and memory management
+    pub other: u64,
+}
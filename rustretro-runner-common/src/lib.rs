@@ -0,0 +1,28 @@
+//! The host side shared by the runners and the frontends. Cores never link this crate.
+
+use rustretro_plugin::Metadata;
+
+mod abi_version;
+mod core_runtime;
+mod fuel_budget;
+mod save_data_tracker;
+
+pub use abi_version::*;
+pub use core_runtime::*;
+pub use fuel_budget::*;
+pub use save_data_tracker::*;
+
+/// Returning tuples is not well supported yet, so the exports return vectors as a u64 holding the
+/// pointer in the low bits and the length in the high bits. Returns `(ptr, length)`.
+pub fn split_return_pointer(ptr: u64) -> (u32, u32) {
+    ((ptr & 0xFFFFFFFF) as u32, (ptr >> 32) as u32)
+}
+
+/// If `length` bytes starting at `offset` are inside a region of `Metadata::memory_regions`.
+/// The runners check it before calling into the core.
+pub fn is_valid_memory_range(metadata: &Metadata, region: u32, offset: u32, length: usize) -> bool {
+    matches!(
+        metadata.memory_regions.get(region as usize),
+        Some(memory_region) if memory_region.contains(offset, length)
+    )
+}
@@ -0,0 +1,53 @@
+type SaveDataHandler = Box<dyn FnMut(&[u8]) + Send>;
+
+/// Keeps the last save data of a core on the host, to only report it when it changes and to
+/// recreate the emulator from it
+pub struct SaveDataTracker {
+    last: Option<Vec<u8>>,
+    handler: Option<SaveDataHandler>,
+}
+
+impl SaveDataTracker {
+    /// Starts from the save data the core was created with
+    pub fn new(save_data: Option<&[u8]>) -> Self {
+        Self {
+            last: save_data.map(|data| data.to_vec()),
+            handler: None,
+        }
+    }
+
+    pub fn last(&self) -> Option<&[u8]> {
+        self.last.as_deref()
+    }
+
+    /// Returns the save data fetched from the core only if it changed since the last time
+    pub fn update(&mut self, data: Option<Vec<u8>>) -> Option<Vec<u8>> {
+        // No save data is sent as an empty buffer
+        let data = data.filter(|data| !data.is_empty())?;
+
+        if self.last.as_ref() == Some(&data) {
+            return None;
+        }
+
+        self.last = Some(data.clone());
+        Some(data)
+    }
+
+    pub fn set_handler(&mut self, handler: impl FnMut(&[u8]) + Send + 'static) {
+        self.handler = Some(Box::new(handler));
+    }
+
+    /// Fetching the save data is only worth it when there is a handler to receive it
+    pub fn has_handler(&self) -> bool {
+        self.handler.is_some()
+    }
+
+    /// Sends the save data fetched from the core to the handler if it changed
+    pub fn flush(&mut self, data: Option<Vec<u8>>) {
+        if let Some(data) = self.update(data) {
+            if let Some(handler) = self.handler.as_mut() {
+                handler(&data);
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::fmt;
+
+use rustretro_plugin::serde_json;
+use rustretro_plugin::Capabilities;
+use wasmi::core::{Trap, TrapCode};
+
+#[derive(Debug)]
+pub enum RunnerError {
+    /// The core isn't a valid WebAssembly module
+    Compile(wasmi::Error),
+    /// The module couldn't be instantiated
+    Instantiate(wasmi::Error),
+    /// The core doesn't export a function required by the ABI, or it has the wrong signature
+    MissingExport(String),
+    /// The core was built for another version of the ABI
+    AbiVersion { core: u32, host: u32 },
+    /// The core refused to start, with its own message
+    CreateCore(String),
+    /// The core trapped during a call
+    Trap(Trap),
+    /// The core used all the fuel given to the call
+    OutOfFuel,
+    /// A previous call was aborted, so the core must be reloaded before it is called again
+    Aborted,
+    /// The core doesn't implement this optional feature
+    Unsupported(Capabilities),
+    /// The range isn't inside one of the memory regions the core declared
+    InvalidMemoryRange {
+        region: u32,
+        offset: u32,
+        length: usize,
+    },
+    /// The core returned a buffer that is outside of its memory
+    InvalidPointer { ptr: u32, length: u32 },
+    /// The core returned a buffer larger than what its metadata allows
+    BufferTooLarge { length: u32, max_length: usize },
+    /// The core returned metadata that couldn't be parsed
+    InvalidMetadata(serde_json::Error),
+}
+
+impl fmt::Display for RunnerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Compile(e) => write!(f, "could not compile the core: {}", e),
+            Self::Instantiate(e) => write!(f, "could not instantiate the core: {}", e),
+            Self::MissingExport(name) => {
+                write!(
+                    f,
+                    "the core doesn't export `{}` or its signature is wrong",
+                    name
+                )
+            }
+            Self::AbiVersion { core, host } => write!(
+                f,
+                "the core was built for version {} of the ABI, but the runner expects version {}",
+                core, host
+            ),
+            Self::CreateCore(e) => write!(f, "the core could not be created: {}", e),
+            Self::Trap(trap) => write!(f, "the core trapped: {}", trap),
+            Self::OutOfFuel => write!(f, "the core ran out of fuel"),
+            Self::Aborted => write!(f, "the core must be reloaded after an aborted call"),
+            Self::Unsupported(capability) => {
+                write!(f, "the core doesn't support {:?}", capability)
+            }
+            Self::InvalidMemoryRange {
+                region,
+                offset,
+                length,
+            } => write!(
+                f,
+                "the core didn't declare {} bytes at {:#x} in its memory region {}",
+                length, offset, region
+            ),
+            Self::InvalidPointer { ptr, length } => write!(
+                f,
+                "the core returned an invalid buffer of {} bytes at {:#x}",
+                length, ptr
+            ),
+            Self::BufferTooLarge { length, max_length } => write!(
+                f,
+                "the core returned a buffer of {} bytes, but at most {} are allowed",
+                length, max_length
+            ),
+            Self::InvalidMetadata(e) => write!(f, "the core returned invalid metadata: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RunnerError {}
+
+impl From<Trap> for RunnerError {
+    fn from(trap: Trap) -> Self {
+        if matches!(trap.trap_code(), Some(TrapCode::OutOfFuel)) {
+            return Self::OutOfFuel;
+        }
+
+        Self::Trap(trap)
+    }
+}
@@ -0,0 +1,356 @@
+use rustretro_plugin::serde_json;
+use rustretro_plugin::{Capabilities, Metadata, ABI_VERSION};
+use rustretro_runner_common::{split_return_pointer, FuelBudget};
+use wasmi::*;
+
+use crate::RunnerError;
+
+/// Largest metadata or error message accepted from a core
+const MAX_MESSAGE_LENGTH: usize = 0x10000;
+
+/// An instance of the core, with the emulator created inside of it
+pub(crate) struct Guest {
+    pub emulator_pointer: u32,
+    pub metadata: Metadata,
+    pub capabilities: Capabilities,
+    /// Fuel burned by all the calls into this instance
+    pub fuel_consumed: u64,
+    pub fuel: Option<FuelBudget>,
+    /// A call ran out of fuel, leaving the core in an unknown state. It must be reloaded before
+    /// anything else is called.
+    pub aborted: bool,
+
+    store: Store<()>,
+    instance: Instance,
+    memory: Memory,
+
+    wasm_alloc_vec: TypedFunc<u32, u32>,
+    wasm_free_vec: TypedFunc<(u32, u32), ()>,
+    pub wasm_controller_input: TypedFunc<(u32, u32, u32, u32), ()>,
+    pub wasm_keyboard_input: TypedFunc<(u32, u32, u32, u32), ()>,
+    pub wasm_clock_until_frame: TypedFunc<u32, u64>,
+    pub wasm_reset: TypedFunc<(u32, u32), ()>,
+    pub wasm_audio_samples: TypedFunc<u32, u64>,
+    pub wasm_save_data: TypedFunc<u32, u64>,
+    pub wasm_save_state: TypedFunc<u32, u64>,
+    pub wasm_load_state: TypedFunc<(u32, u32, u32), ()>,
+    pub wasm_read_memory: TypedFunc<(u32, u32, u32, u32), u64>,
+    pub wasm_write_memory: TypedFunc<(u32, u32, u32, u32, u32), ()>,
+    pub wasm_free_emulator: TypedFunc<u32, ()>,
+}
+
+pub(crate) struct WasmVec {
+    pub ptr: u32,
+    pub length: u32,
+}
+
+impl Guest {
+    /// Instantiate the module and create the emulator
+    pub fn new(
+        engine: &Engine,
+        module: &Module,
+        rom: &[u8],
+        save_data: Option<&[u8]>,
+        fuel: Option<FuelBudget>,
+    ) -> Result<Self, RunnerError> {
+        let mut store = Store::new(engine, ());
+
+        // The start function and the handshake also need fuel
+        if let Some(budget) = fuel {
+            set_fuel(&mut store, budget.other);
+        }
+
+        let instance = Linker::<()>::new(engine)
+            .instantiate(&mut store, module)
+            .and_then(|instance| instance.start(&mut store))
+            .map_err(RunnerError::Instantiate)?;
+
+        // A core built for another ABI can have other exports, so check it before looking them up
+        let capabilities = handshake(&instance, &mut store)?;
+
+        // The default memory is simply called "memory"
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or_else(|| RunnerError::MissingExport("memory".to_string()))?;
+
+        let mut guest = Self {
+            emulator_pointer: 0,
+            metadata: Metadata::default(),
+            capabilities,
+            fuel_consumed: 0,
+            fuel,
+            aborted: false,
+
+            wasm_alloc_vec: get_typed_func(&instance, &store, "__rustretro_plugin_alloc_vec")?,
+            wasm_free_vec: get_typed_func(&instance, &store, "__rustretro_plugin_free_vec")?,
+            wasm_controller_input: get_typed_func(
+                &instance,
+                &store,
+                "__rustretro_plugin_controller_input",
+            )?,
+            wasm_keyboard_input: get_typed_func(
+                &instance,
+                &store,
+                "__rustretro_plugin_keyboard_input",
+            )?,
+            wasm_clock_until_frame: get_typed_func(
+                &instance,
+                &store,
+                "__rustretro_plugin_clock_until_frame",
+            )?,
+            wasm_reset: get_typed_func(&instance, &store, "__rustretro_plugin_reset")?,
+            wasm_audio_samples: get_typed_func(
+                &instance,
+                &store,
+                "__rustretro_plugin_audio_samples",
+            )?,
+            wasm_save_data: get_typed_func(&instance, &store, "__rustretro_plugin_save_data")?,
+            wasm_save_state: get_typed_func(&instance, &store, "__rustretro_plugin_save_state")?,
+            wasm_load_state: get_typed_func(&instance, &store, "__rustretro_plugin_load_state")?,
+            wasm_read_memory: get_typed_func(&instance, &store, "__rustretro_plugin_read_memory")?,
+            wasm_write_memory: get_typed_func(
+                &instance,
+                &store,
+                "__rustretro_plugin_write_memory",
+            )?,
+            wasm_free_emulator: get_typed_func(
+                &instance,
+                &store,
+                "__rustretro_plugin_free_emulator",
+            )?,
+
+            store,
+            instance,
+            memory,
+        };
+
+        guest.create_core(rom, save_data)?;
+
+        // Fetch the core metadata
+        let wasm_get_metadata = get_typed_func::<u32, u64>(
+            &guest.instance,
+            &guest.store,
+            "__rustretro_plugin_get_metadata",
+        )?;
+        let metadata_bytes = guest.fetch_vec(wasm_get_metadata, MAX_MESSAGE_LENGTH)?;
+
+        guest.metadata =
+            serde_json::from_slice(&metadata_bytes).map_err(RunnerError::InvalidMetadata)?;
+
+        Ok(guest)
+    }
+
+    fn create_core(&mut self, rom: &[u8], save_data: Option<&[u8]>) -> Result<(), RunnerError> {
+        let wasm_create_core = get_typed_func::<(u32, u32, u32, u32), u32>(
+            &self.instance,
+            &self.store,
+            "__rustretro_plugin_create_core",
+        )?;
+
+        // Copy the rom and the save data to WASM memory. An empty buffer means there is no save data
+        let rom_buffer = self.write_vec(rom)?;
+        let save_buffer = self.write_vec(save_data.unwrap_or_default())?;
+
+        // Instanciate the emulator
+        let fuel = self.fuel.map(|budget| budget.create_core);
+        let emulator_pointer = self.call_with_fuel(
+            wasm_create_core,
+            (
+                rom_buffer.ptr,
+                rom_buffer.length,
+                save_buffer.ptr,
+                save_buffer.length,
+            ),
+            fuel,
+        )?;
+
+        // Free the ROM and save buffers
+        self.free_vec(rom_buffer)?;
+        self.free_vec(save_buffer)?;
+
+        // A null pointer means the core refused to start
+        if emulator_pointer == 0 {
+            let wasm_take_error = get_typed_func::<(), u64>(
+                &self.instance,
+                &self.store,
+                "__rustretro_plugin_take_error",
+            )?;
+
+            let ptr = self.call(wasm_take_error, ())?;
+            let error_bytes = self.read_vec(expand_return_pointer(ptr), MAX_MESSAGE_LENGTH)?;
+
+            return Err(RunnerError::CreateCore(
+                String::from_utf8_lossy(&error_bytes).into_owned(),
+            ));
+        }
+
+        self.emulator_pointer = emulator_pointer;
+
+        Ok(())
+    }
+
+    /// Call into the core with the default fuel budget
+    pub fn call<Params, Results>(
+        &mut self,
+        func: TypedFunc<Params, Results>,
+        params: Params,
+    ) -> Result<Results, RunnerError>
+    where
+        Params: WasmParams,
+        Results: WasmResults,
+    {
+        let fuel = self.fuel.map(|budget| budget.other);
+        self.call_with_fuel(func, params, fuel)
+    }
+
+    /// Call into the core with exactly `fuel` to spend, or without any limit if it is `None`
+    pub fn call_with_fuel<Params, Results>(
+        &mut self,
+        func: TypedFunc<Params, Results>,
+        params: Params,
+        fuel: Option<u64>,
+    ) -> Result<Results, RunnerError>
+    where
+        Params: WasmParams,
+        Results: WasmResults,
+    {
+        if self.aborted {
+            return Err(RunnerError::Aborted);
+        }
+
+        if let Some(fuel) = fuel {
+            set_fuel(&mut self.store, fuel);
+        }
+
+        let result = func.call(&mut self.store, params);
+
+        if let Some(fuel) = fuel {
+            let remaining = self.store.consume_fuel(0).unwrap_or_default();
+            self.fuel_consumed += fuel - remaining;
+        }
+
+        let result = result.map_err(RunnerError::from);
+        if matches!(result, Err(RunnerError::OutOfFuel)) {
+            self.aborted = true;
+        }
+
+        result
+    }
+
+    /// Call a function of the emulator that returns a vector, and copy it out
+    pub fn fetch_vec(
+        &mut self,
+        func: TypedFunc<u32, u64>,
+        max_length: usize,
+    ) -> Result<Vec<u8>, RunnerError> {
+        let ptr = self.call(func, self.emulator_pointer)?;
+
+        self.read_vec(expand_return_pointer(ptr), max_length)
+    }
+
+    pub fn free_vec(&mut self, wasm_vec: WasmVec) -> Result<(), RunnerError> {
+        self.call(self.wasm_free_vec, (wasm_vec.ptr, wasm_vec.length))
+    }
+
+    /// Allocate a vector in the WASM memory and copy `data` into it
+    pub fn write_vec(&mut self, data: &[u8]) -> Result<WasmVec, RunnerError> {
+        let length = data.len() as u32;
+        let ptr = self.call(self.wasm_alloc_vec, length)?;
+
+        self.memory
+            .write(&mut self.store, ptr as usize, data)
+            .map_err(|_| RunnerError::InvalidPointer { ptr, length })?;
+
+        Ok(WasmVec { ptr, length })
+    }
+
+    /// Copy a vector out of the WASM memory and free it.
+    /// The range is checked against the guest memory before anything is allocated on the host.
+    pub fn read_vec(
+        &mut self,
+        wasm_vec: WasmVec,
+        max_length: usize,
+    ) -> Result<Vec<u8>, RunnerError> {
+        let start = wasm_vec.ptr as usize;
+        let range = start
+            .checked_add(wasm_vec.length as usize)
+            .and_then(|end| self.memory.data(&self.store).get(start..end));
+
+        // The core can't be trusted to free a vector it doesn't own
+        let data = range.ok_or(RunnerError::InvalidPointer {
+            ptr: wasm_vec.ptr,
+            length: wasm_vec.length,
+        })?;
+
+        if data.len() > max_length {
+            let length = wasm_vec.length;
+            self.free_vec(wasm_vec)?;
+
+            return Err(RunnerError::BufferTooLarge { length, max_length });
+        }
+
+        let buffer = data.to_vec();
+
+        self.free_vec(wasm_vec)?;
+
+        Ok(buffer)
+    }
+}
+
+/// Make sure the core was built for this ABI before calling anything else, and read its
+/// capabilities. This runs on the fuel given to the instantiation.
+fn handshake(instance: &Instance, store: &mut Store<()>) -> Result<Capabilities, RunnerError> {
+    let wasm_abi_version =
+        get_typed_func::<(), u32>(instance, store, "__rustretro_plugin_abi_version")?;
+
+    let version = wasm_abi_version.call(&mut *store, ())?;
+    if version != ABI_VERSION {
+        return Err(RunnerError::AbiVersion {
+            core: version,
+            host: ABI_VERSION,
+        });
+    }
+
+    let wasm_capabilities =
+        get_typed_func::<(), u32>(instance, store, "__rustretro_plugin_capabilities")?;
+
+    // Unknown capabilities come from a newer core, and the host can't use them anyway
+    let capabilities = wasm_capabilities.call(&mut *store, ())?;
+    Ok(Capabilities::from_bits_truncate(capabilities))
+}
+
+fn get_typed_func<Params, Results>(
+    instance: &Instance,
+    store: &Store<()>,
+    name: &str,
+) -> Result<TypedFunc<Params, Results>, RunnerError>
+where
+    Params: WasmParams,
+    Results: WasmResults,
+{
+    instance
+        .get_typed_func::<Params, Results>(store, name)
+        .map_err(|_| RunnerError::MissingExport(name.to_string()))
+}
+
+/// Leave exactly `fuel` in the store, whatever the last call didn't use
+fn set_fuel(store: &mut Store<()>, fuel: u64) {
+    let remaining = store
+        .consume_fuel(0)
+        .expect("fuel should be enabled in the engine");
+
+    if remaining < fuel {
+        store
+            .add_fuel(fuel - remaining)
+            .expect("fuel should be enabled in the engine");
+    } else {
+        store
+            .consume_fuel(remaining - fuel)
+            .expect("there should be enough fuel to consume");
+    }
+}
+
+pub(crate) fn expand_return_pointer(ptr: u64) -> WasmVec {
+    let (ptr, length) = split_return_pointer(ptr);
+    WasmVec { ptr, length }
+}
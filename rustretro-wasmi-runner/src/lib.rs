@@ -0,0 +1,420 @@
+//! Runs cores with the wasmi interpreter, for platforms where JIT compilation isn't allowed.
+//! It implements the same ABI as the wasmtime runner, so both can be checked against each other.
+
+use rustretro_plugin::serde_json;
+use rustretro_plugin::{
+    Capabilities, DeviceInput, DeviceType, KeyboardEvent, Metadata, ResetKind, ABI_VERSION,
+};
+use rustretro_runner_common::{
+    is_valid_memory_range, read_abi_version, CoreRuntime, SaveDataTracker,
+};
+use wasmi::{Config, Engine, Module};
+
+mod error;
+mod guest;
+
+pub use error::*;
+pub use rustretro_runner_common::FuelBudget;
+
+use guest::{expand_return_pointer, Guest};
+
+pub struct Runner {
+    engine: Engine,
+    module: Module,
+    rom: Vec<u8>,
+
+    save_data: SaveDataTracker,
+
+    guest: Guest,
+}
+
+impl Drop for Runner {
+    fn drop(&mut self) {
+        // An aborted core can't be called anymore, and its save data may come from half a frame
+        if self.guest.aborted {
+            return;
+        }
+
+        // Don't lose the save data that changed since the last flush
+        let _ = self.flush_save_data();
+
+        // There is nothing left to do if the core fails here
+        let _ = self
+            .guest
+            .call(self.guest.wasm_free_emulator, self.guest.emulator_pointer);
+    }
+}
+
+impl Runner {
+    /// Load a core and create the emulator. Calls into the core are not limited, so a core stuck in
+    /// a loop blocks the caller forever.
+    pub fn new(core: &[u8], rom: &[u8], save_data: Option<&[u8]>) -> Result<Self, RunnerError> {
+        Self::create(core, rom, save_data, None)
+    }
+
+    /// Meter the core with fuel, so a call is interrupted at the exact same point on every machine
+    pub fn with_fuel(
+        core: &[u8],
+        rom: &[u8],
+        save_data: Option<&[u8]>,
+        budget: FuelBudget,
+    ) -> Result<Self, RunnerError> {
+        Self::create(core, rom, save_data, Some(budget))
+    }
+
+    fn create(
+        core: &[u8],
+        rom: &[u8],
+        save_data: Option<&[u8]>,
+        fuel: Option<FuelBudget>,
+    ) -> Result<Self, RunnerError> {
+        let mut config = Config::default();
+        config.consume_fuel(fuel.is_some());
+
+        // Refuse a core built for another ABI before compiling it, if it says which one
+        if let Some(version) = read_abi_version(core) {
+            if version != ABI_VERSION {
+                return Err(RunnerError::AbiVersion {
+                    core: version,
+                    host: ABI_VERSION,
+                });
+            }
+        }
+
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, core).map_err(RunnerError::Compile)?;
+
+        let guest = Guest::new(&engine, &module, rom, save_data, fuel)?;
+
+        Ok(Self {
+            engine,
+            module,
+            rom: rom.to_vec(),
+
+            save_data: SaveDataTracker::new(save_data),
+
+            guest,
+        })
+    }
+
+    /// Change the fuel given to the next calls. This has no effect on a runner created without fuel.
+    pub fn set_fuel_budget(&mut self, budget: FuelBudget) {
+        if self.guest.fuel.is_some() {
+            self.guest.fuel = Some(budget);
+        }
+    }
+
+    /// Fuel burned by the core since it was loaded, or `None` if it isn't metered with fuel
+    pub fn fuel_consumed(&self) -> Option<u64> {
+        self.guest.fuel.map(|_| self.guest.fuel_consumed)
+    }
+
+    /// Recreate the emulator in a fresh instance of the core, starting from the last save data
+    /// fetched. This is how to recover from a call that ran out of fuel.
+    pub fn reload(&mut self) -> Result<(), RunnerError> {
+        self.guest = Guest::new(
+            &self.engine,
+            &self.module,
+            &self.rom,
+            self.save_data.last(),
+            self.guest.fuel,
+        )?;
+
+        Ok(())
+    }
+
+    /// Input on a port the core doesn't have is ignored, like an unplugged controller
+    pub fn controller_input(&mut self, port: u32, input: &DeviceInput) -> Result<(), RunnerError> {
+        if port as usize >= self.guest.metadata.ports.len() {
+            return Ok(());
+        }
+
+        let input = serde_json::to_vec(input).expect("the input should always serialize");
+        let input_buffer = self.guest.write_vec(&input)?;
+
+        self.guest.call(
+            self.guest.wasm_controller_input,
+            (
+                self.guest.emulator_pointer,
+                port,
+                input_buffer.ptr,
+                input_buffer.length,
+            ),
+        )?;
+
+        self.guest.free_vec(input_buffer)
+    }
+
+    /// Events on a port without a keyboard are ignored
+    pub fn keyboard_input(&mut self, port: u32, event: KeyboardEvent) -> Result<(), RunnerError> {
+        let device = self.guest.metadata.ports.get(port as usize);
+
+        if !self.capabilities().contains(Capabilities::KEYBOARD)
+            || device.map(|device| device.device_type) != Some(DeviceType::Keyboard)
+        {
+            return Ok(());
+        }
+
+        self.guest.call(
+            self.guest.wasm_keyboard_input,
+            (
+                self.guest.emulator_pointer,
+                port,
+                event.keycode as u32,
+                event.pressed as u32,
+            ),
+        )
+    }
+
+    /// Reset the console, keeping the save data
+    pub fn reset(&mut self, kind: ResetKind) -> Result<(), RunnerError> {
+        if !self.capabilities().contains(Capabilities::RESET) {
+            return Err(RunnerError::Unsupported(Capabilities::RESET));
+        }
+
+        self.guest.call(
+            self.guest.wasm_reset,
+            (self.guest.emulator_pointer, kind.bits()),
+        )
+    }
+
+    pub fn clock_until_frame(&mut self) -> Result<Vec<u8>, RunnerError> {
+        let metadata = &self.guest.metadata;
+        let max_length = (metadata.width as usize)
+            .saturating_mul(metadata.height as usize)
+            .saturating_mul(metadata.pixel_format.bytes_per_pixel() as usize);
+
+        let fuel = self.guest.fuel.map(|budget| budget.clock_until_frame);
+        let ptr = self.guest.call_with_fuel(
+            self.guest.wasm_clock_until_frame,
+            self.guest.emulator_pointer,
+            fuel,
+        )?;
+
+        self.guest.read_vec(expand_return_pointer(ptr), max_length)
+    }
+
+    /// Fetch the interleaved PCM samples generated during the last frame
+    pub fn audio_samples(&mut self) -> Result<Vec<i16>, RunnerError> {
+        if !self.capabilities().contains(Capabilities::AUDIO) {
+            return Ok(Vec::new());
+        }
+
+        // A frame can't hold more than a second of audio
+        let metadata = &self.guest.metadata;
+        let max_length = (metadata.audio_sample_rate as usize)
+            .saturating_mul(metadata.audio_channels as usize)
+            .saturating_mul(std::mem::size_of::<i16>());
+
+        Ok(self
+            .guest
+            .fetch_vec(self.guest.wasm_audio_samples, max_length)?
+            .chunks_exact(2)
+            .map(|s| i16::from_le_bytes([s[0], s[1]]))
+            .collect())
+    }
+
+    /// Fetch the battery-backed RAM, only if it changed since the last time it was fetched
+    pub fn dirty_save_data(&mut self) -> Result<Option<Vec<u8>>, RunnerError> {
+        let data = self.fetch_save_data()?;
+        Ok(self.save_data.update(data))
+    }
+
+    /// Set the function that receives the save data when it is flushed
+    pub fn on_save_data(&mut self, handler: impl FnMut(&[u8]) + Send + 'static) {
+        self.save_data.set_handler(handler);
+    }
+
+    /// Send the save data to the handler if it changed since the last flush.
+    /// This is also done when the runner is dropped.
+    pub fn flush_save_data(&mut self) -> Result<(), RunnerError> {
+        if self.save_data.has_handler() {
+            let data = self.fetch_save_data()?;
+            self.save_data.flush(data);
+        }
+
+        Ok(())
+    }
+
+    fn fetch_save_data(&mut self) -> Result<Option<Vec<u8>>, RunnerError> {
+        if !self.capabilities().contains(Capabilities::SAVE_DATA) {
+            return Ok(None);
+        }
+
+        // The save data is only bounded by the guest memory
+        let data = self
+            .guest
+            .fetch_vec(self.guest.wasm_save_data, usize::MAX)?;
+        Ok(Some(data))
+    }
+
+    /// Serialize the emulator state
+    pub fn save_state(&mut self) -> Result<Vec<u8>, RunnerError> {
+        if !self.capabilities().contains(Capabilities::SAVE_STATE) {
+            return Err(RunnerError::Unsupported(Capabilities::SAVE_STATE));
+        }
+
+        // The state is only bounded by the guest memory
+        self.guest.fetch_vec(self.guest.wasm_save_state, usize::MAX)
+    }
+
+    /// Restore a state previously returned by `save_state`
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), RunnerError> {
+        if !self.capabilities().contains(Capabilities::SAVE_STATE) {
+            return Err(RunnerError::Unsupported(Capabilities::SAVE_STATE));
+        }
+
+        let state_buffer = self.guest.write_vec(state)?;
+
+        self.guest.call(
+            self.guest.wasm_load_state,
+            (
+                self.guest.emulator_pointer,
+                state_buffer.ptr,
+                state_buffer.length,
+            ),
+        )?;
+
+        self.guest.free_vec(state_buffer)
+    }
+
+    /// Copy `length` bytes of a region of `Metadata::memory_regions`, starting at `offset`
+    pub fn read_memory(
+        &mut self,
+        region: u32,
+        offset: u32,
+        length: u32,
+    ) -> Result<Vec<u8>, RunnerError> {
+        self.check_memory_range(region, offset, length as usize)?;
+
+        let ptr = self.guest.call(
+            self.guest.wasm_read_memory,
+            (self.guest.emulator_pointer, region, offset, length),
+        )?;
+
+        self.guest
+            .read_vec(expand_return_pointer(ptr), length as usize)
+    }
+
+    /// Overwrite a region of `Metadata::memory_regions`, starting at `offset`
+    pub fn write_memory(
+        &mut self,
+        region: u32,
+        offset: u32,
+        data: &[u8],
+    ) -> Result<(), RunnerError> {
+        self.check_memory_range(region, offset, data.len())?;
+
+        let data_buffer = self.guest.write_vec(data)?;
+
+        self.guest.call(
+            self.guest.wasm_write_memory,
+            (
+                self.guest.emulator_pointer,
+                region,
+                offset,
+                data_buffer.ptr,
+                data_buffer.length,
+            ),
+        )?;
+
+        self.guest.free_vec(data_buffer)
+    }
+
+    /// The core is promised to only get ranges inside the regions it declared
+    fn check_memory_range(
+        &self,
+        region: u32,
+        offset: u32,
+        length: usize,
+    ) -> Result<(), RunnerError> {
+        if !self.capabilities().contains(Capabilities::MEMORY_ACCESS) {
+            return Err(RunnerError::Unsupported(Capabilities::MEMORY_ACCESS));
+        }
+
+        if !is_valid_memory_range(&self.guest.metadata, region, offset, length) {
+            return Err(RunnerError::InvalidMemoryRange {
+                region,
+                offset,
+                length,
+            });
+        }
+
+        Ok(())
+    }
+
+    pub fn get_metadata(&self) -> &Metadata {
+        &self.guest.metadata
+    }
+
+    /// The optional features the core advertised
+    pub fn capabilities(&self) -> Capabilities {
+        self.guest.capabilities
+    }
+}
+
+impl CoreRuntime for Runner {
+    type Error = RunnerError;
+
+    fn get_metadata(&self) -> &Metadata {
+        Runner::get_metadata(self)
+    }
+
+    fn controller_input(&mut self, port: u32, input: &DeviceInput) -> Result<(), RunnerError> {
+        Runner::controller_input(self, port, input)
+    }
+
+    fn clock_until_frame(&mut self) -> Result<Vec<u8>, RunnerError> {
+        Runner::clock_until_frame(self)
+    }
+
+    fn keyboard_input(&mut self, port: u32, event: KeyboardEvent) -> Result<(), RunnerError> {
+        Runner::keyboard_input(self, port, event)
+    }
+
+    fn reset(&mut self, kind: ResetKind) -> Result<(), RunnerError> {
+        Runner::reset(self, kind)
+    }
+
+    fn audio_samples(&mut self) -> Result<Vec<i16>, RunnerError> {
+        Runner::audio_samples(self)
+    }
+
+    fn flush_save_data(&mut self) -> Result<(), RunnerError> {
+        Runner::flush_save_data(self)
+    }
+
+    fn save_state(&mut self) -> Result<Vec<u8>, RunnerError> {
+        Runner::save_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), RunnerError> {
+        Runner::load_state(self, state)
+    }
+
+    fn read_memory(
+        &mut self,
+        region: u32,
+        offset: u32,
+        length: u32,
+    ) -> Result<Vec<u8>, RunnerError> {
+        Runner::read_memory(self, region, offset, length)
+    }
+
+    fn write_memory(&mut self, region: u32, offset: u32, data: &[u8]) -> Result<(), RunnerError> {
+        Runner::write_memory(self, region, offset, data)
+    }
+
+    fn reload(&mut self) -> Result<(), RunnerError> {
+        Runner::reload(self)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Runner::capabilities(self)
+    }
+
+    /// Only a call that ran out of fuel is worth a reload, any other error would happen again
+    fn is_recoverable(&self, error: &RunnerError) -> bool {
+        matches!(error, RunnerError::OutOfFuel | RunnerError::Aborted)
+    }
+}
@@ -0,0 +1,176 @@
+use std::path::PathBuf;
+
+use wasmtime::OptLevel;
+
+use crate::timeout::Timeout;
+use crate::{CoreSource, EngineOptions, FuelBudget, ResourceLimits, Runner, RunnerError, Runtime};
+
+/// How a call into the core is interrupted
+#[derive(Clone, Copy, Debug)]
+pub enum TimeoutStrategy {
+    /// Wall-clock time limit of each call, in milliseconds
+    Epoch(u64),
+    /// Deterministic fuel budget of each call
+    Fuel(FuelBudget),
+}
+
+enum CacheLocation {
+    Default,
+    Dir(PathBuf),
+    Disabled,
+}
+
+/// Configures the engine and the sandbox of a runner
+pub struct RunnerBuilder {
+    options: EngineOptions,
+    cache: CacheLocation,
+    runtime: Option<Runtime>,
+    limits: ResourceLimits,
+    timeout: TimeoutStrategy,
+}
+
+impl Default for RunnerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RunnerBuilder {
+    pub fn new() -> Self {
+        Self {
+            options: EngineOptions::default(),
+            cache: CacheLocation::Default,
+            runtime: None,
+            limits: ResourceLimits::default(),
+            timeout: TimeoutStrategy::Epoch(1000),
+        }
+    }
+
+    pub fn opt_level(mut self, opt_level: OptLevel) -> Self {
+        self.options.opt_level = opt_level;
+        self
+    }
+
+    pub fn simd(mut self, enable: bool) -> Self {
+        self.options.simd = enable;
+        self
+    }
+
+    pub fn bulk_memory(mut self, enable: bool) -> Self {
+        self.options.bulk_memory = enable;
+        self
+    }
+
+    pub fn threads(mut self, enable: bool) -> Self {
+        self.options.threads = enable;
+        self
+    }
+
+    pub fn debug_info(mut self, enable: bool) -> Self {
+        self.options.debug_info = enable;
+        self
+    }
+
+    /// Directory of the compiled cores. Defaults to the user's cache directory
+    pub fn cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cache = CacheLocation::Dir(dir.into());
+        self
+    }
+
+    pub fn disable_cache(mut self) -> Self {
+        self.cache = CacheLocation::Disabled;
+        self
+    }
+
+    pub fn limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Defaults to a wall-clock limit of one second per call
+    pub fn timeout(mut self, timeout: TimeoutStrategy) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Run in an existing runtime instead of creating one.
+    /// Its engine is already configured, so the engine and cache options are ignored.
+    pub fn runtime(mut self, runtime: &Runtime) -> Self {
+        self.runtime = Some(runtime.clone());
+        self
+    }
+
+    /// Create a runtime from the engine and cache options, to share it between runners
+    pub fn build_runtime(&self) -> Result<Runtime, RunnerError> {
+        let options = EngineOptions {
+            fuel: matches!(self.timeout, TimeoutStrategy::Fuel(_)),
+            ..self.options.clone()
+        };
+
+        let runtime = Runtime::with_options(options)?;
+
+        Ok(match &self.cache {
+            CacheLocation::Default => runtime,
+            CacheLocation::Dir(dir) => runtime.with_cache_dir(dir.clone()),
+            CacheLocation::Disabled => runtime.without_cache(),
+        })
+    }
+
+    /// Load a core and create the emulator
+    pub fn build(
+        self,
+        core: &[u8],
+        rom: &[u8],
+        save_data: Option<&[u8]>,
+    ) -> Result<Runner, RunnerError> {
+        let runtime = self.resolve_runtime()?;
+        let timeout = Timeout::new(&runtime, self.timeout)?;
+        let module = runtime.module(core)?;
+
+        Runner::create(
+            &runtime,
+            module,
+            CoreSource::Wasm(core),
+            rom,
+            save_data,
+            timeout,
+            self.limits,
+        )
+    }
+
+    /// Same as `build`, from an artifact returned by `Runtime::precompile` instead of the core.
+    /// The engine options must be the same as when it was precompiled.
+    ///
+    /// # Safety
+    ///
+    /// The artifact is native code that runs outside of the sandbox. It must come from
+    /// `Runtime::precompile` and must not have been modified since: wasmtime only checks that it
+    /// was compiled by the same version with the same configuration.
+    pub unsafe fn build_precompiled(
+        self,
+        artifact: &[u8],
+        rom: &[u8],
+        save_data: Option<&[u8]>,
+    ) -> Result<Runner, RunnerError> {
+        let runtime = self.resolve_runtime()?;
+        let timeout = Timeout::new(&runtime, self.timeout)?;
+        let module = runtime.deserialize(artifact)?;
+
+        Runner::create(
+            &runtime,
+            module,
+            CoreSource::Precompiled(artifact),
+            rom,
+            save_data,
+            timeout,
+            self.limits,
+        )
+    }
+
+    fn resolve_runtime(&self) -> Result<Runtime, RunnerError> {
+        match &self.runtime {
+            Some(x) => Ok(x.clone()),
+            None => self.build_runtime(),
+        }
+    }
+}
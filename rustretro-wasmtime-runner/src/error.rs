@@ -0,0 +1,137 @@
+use std::fmt;
+
+use rustretro_plugin::serde_json;
+use rustretro_plugin::Capabilities;
+use wasmtime::Trap;
+
+use crate::ValidationReport;
+
+#[derive(Debug)]
+pub enum RunnerError {
+    /// The engine doesn't support the requested options
+    InvalidConfig(anyhow::Error),
+    /// The core isn't a valid WebAssembly module
+    Compile(anyhow::Error),
+    /// The compiled core couldn't be written to the cache
+    Cache(anyhow::Error),
+    /// The precompiled core was built by another version or with another configuration
+    InvalidArtifact(anyhow::Error),
+    /// The runtime doesn't meter cores the way the runner asked for
+    IncompatibleRuntime,
+    /// The core doesn't have the exports and imports the ABI expects
+    InvalidCore(ValidationReport),
+    /// The module couldn't be instantiated
+    Instantiate(anyhow::Error),
+    /// The core doesn't export a function required by the ABI, or it has the wrong signature
+    MissingExport(String),
+    /// The core was built for another version of the ABI
+    AbiVersion { core: u32, host: u32 },
+    /// The core refused to start, with its own message
+    CreateCore(String),
+    /// The core trapped during a call
+    Trap(Trap),
+    /// The core ran past its time limit and the call was aborted
+    Timeout,
+    /// The core used all the fuel given to the call
+    OutOfFuel,
+    /// A previous call was aborted, so the core must be reloaded before it is called again
+    Aborted,
+    /// The core doesn't implement this optional feature
+    Unsupported(Capabilities),
+    /// The range isn't inside one of the memory regions the core declared
+    InvalidMemoryRange {
+        region: u32,
+        offset: u32,
+        length: usize,
+    },
+    /// The core returned a buffer that is outside of its memory
+    InvalidPointer { ptr: u32, length: u32 },
+    /// The core returned a buffer larger than what its metadata allows
+    BufferTooLarge { length: u32, max_length: usize },
+    /// The core tried to grow its memory past the configured limit
+    MemoryLimit { desired: usize, limit: usize },
+    /// The core tried to grow a table past the configured limit
+    TableLimit { desired: u32, limit: u32 },
+    /// The core returned metadata that couldn't be parsed
+    InvalidMetadata(serde_json::Error),
+    /// The snapshot doesn't fit this instance
+    InvalidSnapshot,
+}
+
+impl fmt::Display for RunnerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidConfig(e) => write!(f, "invalid engine options: {}", e),
+            Self::Compile(e) => write!(f, "could not compile the core: {}", e),
+            Self::Cache(e) => write!(f, "could not cache the compiled core: {}", e),
+            Self::InvalidArtifact(e) => write!(f, "could not load the precompiled core: {}", e),
+            Self::IncompatibleRuntime => {
+                write!(
+                    f,
+                    "the runtime doesn't use the requested time limit or fuel"
+                )
+            }
+            Self::InvalidCore(report) => write!(f, "{}", report),
+            Self::Instantiate(e) => write!(f, "could not instantiate the core: {}", e),
+            Self::MissingExport(name) => {
+                write!(
+                    f,
+                    "the core doesn't export `{}` or its signature is wrong",
+                    name
+                )
+            }
+            Self::AbiVersion { core, host } => write!(
+                f,
+                "the core was built for version {} of the ABI, but the runner expects version {}",
+                core, host
+            ),
+            Self::CreateCore(e) => write!(f, "the core could not be created: {}", e),
+            Self::Trap(trap) => write!(f, "the core trapped: {}", trap),
+            Self::Timeout => write!(f, "the core timed out"),
+            Self::OutOfFuel => write!(f, "the core ran out of fuel"),
+            Self::Aborted => write!(f, "the core must be reloaded after an aborted call"),
+            Self::Unsupported(capability) => {
+                write!(f, "the core doesn't support {:?}", capability)
+            }
+            Self::InvalidMemoryRange {
+                region,
+                offset,
+                length,
+            } => write!(
+                f,
+                "the core didn't declare {} bytes at {:#x} in its memory region {}",
+                length, offset, region
+            ),
+            Self::InvalidPointer { ptr, length } => write!(
+                f,
+                "the core returned an invalid buffer of {} bytes at {:#x}",
+                length, ptr
+            ),
+            Self::BufferTooLarge { length, max_length } => write!(
+                f,
+                "the core returned a buffer of {} bytes, but at most {} are allowed",
+                length, max_length
+            ),
+            Self::MemoryLimit { desired, limit } => write!(
+                f,
+                "the core tried to grow its memory to {} bytes, past the limit of {}",
+                desired, limit
+            ),
+            Self::TableLimit { desired, limit } => write!(
+                f,
+                "the core tried to grow a table to {} elements, past the limit of {}",
+                desired, limit
+            ),
+            Self::InvalidMetadata(e) => write!(f, "the core returned invalid metadata: {}", e),
+            Self::InvalidSnapshot => write!(f, "the snapshot was taken from another core"),
+        }
+    }
+}
+
+impl std::error::Error for RunnerError {}
+
+impl From<Trap> for RunnerError {
+    fn from(trap: Trap) -> Self {
+        Self::Trap(trap)
+    }
+}
@@ -0,0 +1,476 @@
+use rustretro_plugin::serde_json;
+use rustretro_plugin::{Capabilities, Metadata, ABI_VERSION};
+use rustretro_runner_common::split_return_pointer;
+use wasmtime::*;
+
+use crate::limits::{Limiter, ResourceLimits};
+use crate::timeout::Timeout;
+use crate::RunnerError;
+
+/// Largest metadata or error message accepted from a core
+const MAX_MESSAGE_LENGTH: usize = 0x10000;
+
+/// An instance of the core, with the emulator created inside of it
+pub(crate) struct Guest {
+    pub emulator_pointer: u32,
+    pub metadata: Metadata,
+    pub capabilities: Capabilities,
+    /// Fuel burned by all the calls into this instance
+    pub fuel_consumed: u64,
+    /// A call timed out or ran out of fuel, leaving the core in an unknown state. It must be
+    /// reloaded before anything else is called.
+    pub aborted: bool,
+
+    pub store: Store<Limiter>,
+    pub instance: Instance,
+    pub memory: Memory,
+
+    wasm_alloc_vec: TypedFunc<u32, u32>,
+    wasm_free_vec: TypedFunc<(u32, u32), ()>,
+    pub wasm_controller_input: TypedFunc<(u32, u32, u32, u32), ()>,
+    pub wasm_keyboard_input: TypedFunc<(u32, u32, u32, u32), ()>,
+    pub wasm_clock_until_frame: TypedFunc<u32, u64>,
+    pub wasm_reset: TypedFunc<(u32, u32), ()>,
+    pub wasm_audio_samples: TypedFunc<u32, u64>,
+    pub wasm_save_data: TypedFunc<u32, u64>,
+    pub wasm_save_state: TypedFunc<u32, u64>,
+    pub wasm_load_state: TypedFunc<(u32, u32, u32), ()>,
+    pub wasm_read_memory: TypedFunc<(u32, u32, u32, u32), u64>,
+    pub wasm_write_memory: TypedFunc<(u32, u32, u32, u32, u32), ()>,
+    pub wasm_free_emulator: TypedFunc<u32, ()>,
+}
+
+pub(crate) struct WasmVec {
+    pub ptr: u32,
+    pub length: u32,
+}
+
+impl WasmVec {
+    /// Give up on freeing the vector, when the guest can't be trusted to do it anymore
+    pub fn leak(self) {
+        std::mem::forget(self);
+    }
+}
+
+#[cfg(debug_assertions)]
+impl Drop for WasmVec {
+    fn drop(&mut self) {
+        // Detect if a vector hasn't been freed properly
+        panic!("A WASM vec wasn't dropped!");
+    }
+}
+
+impl Guest {
+    /// Instantiate the module and create the emulator
+    pub fn new(
+        engine: &Engine,
+        module: &Module,
+        rom: &[u8],
+        save_data: Option<&[u8]>,
+        timeout: &mut Timeout,
+        limits: ResourceLimits,
+    ) -> Result<Self, RunnerError> {
+        let mut store = Store::new(engine, Limiter::new(limits));
+        store.limiter(|limiter| limiter);
+
+        match timeout.fuel {
+            Some(budget) => set_fuel(&mut store, budget.other),
+            None => {
+                // Past the deadline, yield on every tick so the timeout handler can decide what to do
+                store.epoch_deadline_async_yield_and_update(1);
+                store.set_epoch_deadline(timeout.ms);
+            }
+        }
+
+        let instance = timeout
+            .run(Instance::new_async(&mut store, module, &[]))?
+            .map_err(|e| {
+                // The initial memory or tables can already be too large
+                store
+                    .data_mut()
+                    .take_exceeded()
+                    .unwrap_or(RunnerError::Instantiate(e))
+            })?;
+
+        // The default memory is simply called "memory"
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or_else(|| RunnerError::MissingExport("memory".to_string()))?;
+
+        let mut guest = Self {
+            emulator_pointer: 0,
+            metadata: Metadata::default(),
+            capabilities: Capabilities::empty(),
+            fuel_consumed: 0,
+            aborted: false,
+
+            wasm_alloc_vec: get_typed_func(&instance, &mut store, "__rustretro_plugin_alloc_vec")?,
+            wasm_free_vec: get_typed_func(&instance, &mut store, "__rustretro_plugin_free_vec")?,
+            wasm_controller_input: get_typed_func(
+                &instance,
+                &mut store,
+                "__rustretro_plugin_controller_input",
+            )?,
+            wasm_keyboard_input: get_typed_func(
+                &instance,
+                &mut store,
+                "__rustretro_plugin_keyboard_input",
+            )?,
+            wasm_clock_until_frame: get_typed_func(
+                &instance,
+                &mut store,
+                "__rustretro_plugin_clock_until_frame",
+            )?,
+            wasm_reset: get_typed_func(&instance, &mut store, "__rustretro_plugin_reset")?,
+            wasm_audio_samples: get_typed_func(
+                &instance,
+                &mut store,
+                "__rustretro_plugin_audio_samples",
+            )?,
+            wasm_save_data: get_typed_func(&instance, &mut store, "__rustretro_plugin_save_data")?,
+            wasm_save_state: get_typed_func(
+                &instance,
+                &mut store,
+                "__rustretro_plugin_save_state",
+            )?,
+            wasm_load_state: get_typed_func(
+                &instance,
+                &mut store,
+                "__rustretro_plugin_load_state",
+            )?,
+            wasm_read_memory: get_typed_func(
+                &instance,
+                &mut store,
+                "__rustretro_plugin_read_memory",
+            )?,
+            wasm_write_memory: get_typed_func(
+                &instance,
+                &mut store,
+                "__rustretro_plugin_write_memory",
+            )?,
+            wasm_free_emulator: get_typed_func(
+                &instance,
+                &mut store,
+                "__rustretro_plugin_free_emulator",
+            )?,
+
+            store,
+            instance,
+            memory,
+        };
+
+        guest.handshake(timeout)?;
+        guest.create_core(rom, save_data, timeout)?;
+
+        // Fetch the core metadata
+        let wasm_get_metadata = get_typed_func::<u32, u64>(
+            &guest.instance,
+            &mut guest.store,
+            "__rustretro_plugin_get_metadata",
+        )?;
+        let metadata_bytes = guest.fetch_vec(timeout, wasm_get_metadata, MAX_MESSAGE_LENGTH)?;
+
+        guest.metadata =
+            serde_json::from_slice(&metadata_bytes).map_err(RunnerError::InvalidMetadata)?;
+
+        Ok(guest)
+    }
+
+    /// Make sure the core was built for this ABI before calling anything else
+    fn handshake(&mut self, timeout: &mut Timeout) -> Result<(), RunnerError> {
+        let wasm_abi_version = get_typed_func::<(), u32>(
+            &self.instance,
+            &mut self.store,
+            "__rustretro_plugin_abi_version",
+        )?;
+        let wasm_capabilities = get_typed_func::<(), u32>(
+            &self.instance,
+            &mut self.store,
+            "__rustretro_plugin_capabilities",
+        )?;
+
+        let version = self.call(timeout, wasm_abi_version, ())?;
+        if version != ABI_VERSION {
+            return Err(RunnerError::AbiVersion {
+                core: version,
+                host: ABI_VERSION,
+            });
+        }
+
+        // Unknown capabilities come from a newer core, and the host can't use them anyway
+        let capabilities = self.call(timeout, wasm_capabilities, ())?;
+        self.capabilities = Capabilities::from_bits_truncate(capabilities);
+
+        Ok(())
+    }
+
+    fn create_core(
+        &mut self,
+        rom: &[u8],
+        save_data: Option<&[u8]>,
+        timeout: &mut Timeout,
+    ) -> Result<(), RunnerError> {
+        let wasm_create_core = get_typed_func::<(u32, u32, u32, u32), u32>(
+            &self.instance,
+            &mut self.store,
+            "__rustretro_plugin_create_core",
+        )?;
+
+        // Copy the rom to WASM memory
+        let rom_buffer = self.write_vec(timeout, rom)?;
+
+        // Copy the save data to WASM memory. An empty buffer means there is no save data
+        let save_buffer = match self.write_vec(timeout, save_data.unwrap_or_default()) {
+            Ok(x) => x,
+            Err(e) => {
+                rom_buffer.leak();
+                return Err(e);
+            }
+        };
+
+        // Instanciate the emulator
+        let fuel = timeout.fuel.map(|budget| budget.create_core);
+        let emulator_pointer = match self.call_with_fuel(
+            timeout,
+            wasm_create_core,
+            (
+                rom_buffer.ptr,
+                rom_buffer.length,
+                save_buffer.ptr,
+                save_buffer.length,
+            ),
+            fuel,
+        ) {
+            Ok(x) => x,
+            Err(e) => {
+                rom_buffer.leak();
+                save_buffer.leak();
+                return Err(e);
+            }
+        };
+
+        // Free the ROM and save buffers
+        if let Err(e) = self.free_vec(timeout, rom_buffer) {
+            save_buffer.leak();
+            return Err(e);
+        }
+        self.free_vec(timeout, save_buffer)?;
+
+        // A null pointer means the core refused to start
+        if emulator_pointer == 0 {
+            let wasm_take_error = get_typed_func::<(), u64>(
+                &self.instance,
+                &mut self.store,
+                "__rustretro_plugin_take_error",
+            )?;
+
+            let ptr = self.call(timeout, wasm_take_error, ())?;
+            let error_bytes =
+                self.read_vec(timeout, expand_return_pointer(ptr), MAX_MESSAGE_LENGTH)?;
+
+            return Err(RunnerError::CreateCore(
+                String::from_utf8_lossy(&error_bytes).into_owned(),
+            ));
+        }
+
+        self.emulator_pointer = emulator_pointer;
+
+        Ok(())
+    }
+
+    /// Call into the core within the time limit, or with the default fuel budget
+    pub fn call<Params, Results>(
+        &mut self,
+        timeout: &mut Timeout,
+        func: TypedFunc<Params, Results>,
+        params: Params,
+    ) -> Result<Results, RunnerError>
+    where
+        Params: WasmParams,
+        Results: WasmResults,
+    {
+        let fuel = timeout.fuel.map(|budget| budget.other);
+        self.call_with_fuel(timeout, func, params, fuel)
+    }
+
+    /// Call into the core with exactly `fuel` to spend, or within the time limit if it is `None`
+    pub fn call_with_fuel<Params, Results>(
+        &mut self,
+        timeout: &mut Timeout,
+        func: TypedFunc<Params, Results>,
+        params: Params,
+        fuel: Option<u64>,
+    ) -> Result<Results, RunnerError>
+    where
+        Params: WasmParams,
+        Results: WasmResults,
+    {
+        if self.aborted {
+            return Err(RunnerError::Aborted);
+        }
+
+        match fuel {
+            Some(fuel) => set_fuel(&mut self.store, fuel),
+            None => self.store.set_epoch_deadline(timeout.ms),
+        }
+        self.store.data_mut().take_exceeded();
+
+        let result = match timeout.run(func.call_async(&mut self.store, params)) {
+            Ok(x) => x,
+            Err(e) => {
+                self.aborted = true;
+                return Err(e);
+            }
+        };
+
+        let out_of_fuel = match fuel {
+            Some(fuel) => {
+                let remaining = self.store.consume_fuel(0).unwrap_or_default();
+                self.fuel_consumed += fuel - remaining;
+                remaining == 0
+            }
+            None => false,
+        };
+
+        // A core usually traps when it can't allocate, so report the limit it hit instead
+        result.map_err(|trap| {
+            if out_of_fuel {
+                self.aborted = true;
+                return RunnerError::OutOfFuel;
+            }
+
+            self.store
+                .data_mut()
+                .take_exceeded()
+                .unwrap_or(RunnerError::Trap(trap))
+        })
+    }
+
+    /// Call a function of the emulator that returns a vector, and copy it out
+    pub fn fetch_vec(
+        &mut self,
+        timeout: &mut Timeout,
+        func: TypedFunc<u32, u64>,
+        max_length: usize,
+    ) -> Result<Vec<u8>, RunnerError> {
+        let ptr = self.call(timeout, func, self.emulator_pointer)?;
+
+        self.read_vec(timeout, expand_return_pointer(ptr), max_length)
+    }
+
+    fn alloc_vec(&mut self, timeout: &mut Timeout, length: u32) -> Result<WasmVec, RunnerError> {
+        let ptr = self.call(timeout, self.wasm_alloc_vec, length)?;
+
+        Ok(WasmVec { ptr, length })
+    }
+
+    pub fn free_vec(
+        &mut self,
+        timeout: &mut Timeout,
+        wasm_vec: WasmVec,
+    ) -> Result<(), RunnerError> {
+        let result = self.call(timeout, self.wasm_free_vec, (wasm_vec.ptr, wasm_vec.length));
+
+        std::mem::forget(wasm_vec);
+
+        result
+    }
+
+    /// Allocate a vector in the WASM memory and copy `data` into it
+    pub fn write_vec(
+        &mut self,
+        timeout: &mut Timeout,
+        data: &[u8],
+    ) -> Result<WasmVec, RunnerError> {
+        let wasm_vec = self.alloc_vec(timeout, data.len() as u32)?;
+
+        if self
+            .memory
+            .write(&mut self.store, wasm_vec.ptr as usize, data)
+            .is_err()
+        {
+            let error = RunnerError::InvalidPointer {
+                ptr: wasm_vec.ptr,
+                length: wasm_vec.length,
+            };
+
+            wasm_vec.leak();
+            return Err(error);
+        }
+
+        Ok(wasm_vec)
+    }
+
+    /// Copy a vector out of the WASM memory and free it.
+    /// The range is checked against the guest memory before anything is allocated on the host.
+    pub fn read_vec(
+        &mut self,
+        timeout: &mut Timeout,
+        wasm_vec: WasmVec,
+        max_length: usize,
+    ) -> Result<Vec<u8>, RunnerError> {
+        let start = wasm_vec.ptr as usize;
+        let range = start
+            .checked_add(wasm_vec.length as usize)
+            .and_then(|end| self.memory.data(&self.store).get(start..end));
+
+        if range.is_none() {
+            let error = RunnerError::InvalidPointer {
+                ptr: wasm_vec.ptr,
+                length: wasm_vec.length,
+            };
+
+            // The core can't be trusted to free a vector it doesn't own
+            wasm_vec.leak();
+            return Err(error);
+        }
+
+        if wasm_vec.length as usize > max_length {
+            let length = wasm_vec.length;
+            self.free_vec(timeout, wasm_vec)?;
+
+            return Err(RunnerError::BufferTooLarge { length, max_length });
+        }
+
+        let buffer = range.map(|data| data.to_vec()).unwrap_or_default();
+
+        self.free_vec(timeout, wasm_vec)?;
+
+        Ok(buffer)
+    }
+}
+
+fn get_typed_func<Params, Results>(
+    instance: &Instance,
+    store: &mut Store<Limiter>,
+    name: &str,
+) -> Result<TypedFunc<Params, Results>, RunnerError>
+where
+    Params: WasmParams,
+    Results: WasmResults,
+{
+    instance
+        .get_typed_func::<Params, Results, _>(store, name)
+        .map_err(|_| RunnerError::MissingExport(name.to_string()))
+}
+
+/// Leave exactly `fuel` in the store, whatever the last call didn't use
+fn set_fuel(store: &mut Store<Limiter>, fuel: u64) {
+    // The store refuses to consume anything once a call has run out of fuel, so it is empty
+    let remaining = store.consume_fuel(0).unwrap_or_default();
+
+    if remaining < fuel {
+        store
+            .add_fuel(fuel - remaining)
+            .expect("fuel should be enabled in the engine");
+    } else {
+        store
+            .consume_fuel(remaining - fuel)
+            .expect("there should be enough fuel to consume");
+    }
+}
+
+pub(crate) fn expand_return_pointer(ptr: u64) -> WasmVec {
+    let (ptr, length) = split_return_pointer(ptr);
+    WasmVec { ptr, length }
+}
@@ -1,240 +1,648 @@
-use std::sync::mpsc::Sender;
-use std::thread::JoinHandle;
 use std::time::Duration;
 
 use rustretro_plugin::serde_json;
-use rustretro_plugin::{ControllerInput, Metadata};
+use rustretro_plugin::{Capabilities, DeviceInput, DeviceType, KeyboardEvent, Metadata, ResetKind};
+use rustretro_runner_common::{is_valid_memory_range, CoreRuntime, SaveDataTracker};
+use sha2::{Digest, Sha256};
 use wasmtime::*;
 
-pub struct Runner {
-    emulator_pointer: u32,
+mod builder;
+mod error;
+mod guest;
+mod limits;
+mod runtime;
+mod timeout;
+mod validation;
+
+pub use builder::{RunnerBuilder, TimeoutStrategy};
+pub use error::*;
+pub use limits::ResourceLimits;
+pub use runtime::{EngineOptions, Runtime};
+pub use rustretro_runner_common::FuelBudget;
+pub use timeout::TimeoutAction;
+pub use validation::{MistypedExport, ValidationReport};
+pub use wasmtime::OptLevel;
+
+use guest::{expand_return_pointer, Guest};
+use timeout::Timeout;
+
+const WASM_PAGE_SIZE: usize = 0x10000;
+
+/// What a module was loaded from
+pub(crate) enum CoreSource<'a> {
+    Wasm(&'a [u8]),
+    /// An artifact returned by `Runtime::precompile`
+    Precompiled(&'a [u8]),
+}
 
-    timeout_ms: u64,
-    epoch_handle: Option<JoinHandle<()>>,
-    epoch_stop_sender: Sender<()>,
+pub struct Runner {
+    runtime: Runtime,
+    module: Module,
+    /// SHA-256 of what the module was loaded from, to recognize its snapshots
+    core_digest: [u8; 32],
+    rom: Vec<u8>,
+    limits: ResourceLimits,
 
-    metadata: Metadata,
+    timeout: Timeout,
 
-    store: Store<String>,
-    memory: Memory,
+    save_data: SaveDataTracker,
 
-    _wasm_alloc_vec: TypedFunc<u32, u32>,
-    wasm_controller_input: TypedFunc<(u32, u32), ()>,
-    wasm_clock_until_frame: TypedFunc<u32, u64>,
-    wasm_free_vec: TypedFunc<(u32, u32), ()>,
-    wasm_free_emulator: TypedFunc<u32, ()>,
+    guest: Guest,
 }
 
 impl Drop for Runner {
     fn drop(&mut self) {
-        self.wasm_free_emulator
-            .call(&mut self.store, self.emulator_pointer)
-            .unwrap();
-
-        // Stop the epoch
-        self.epoch_stop_sender
-            .send(())
-            .expect("Sending shouldn't fail if the thread didn't panic");
-        match self.epoch_handle.take() {
-            Some(x) => {
-                x.join().unwrap(); // Unwrap to bubble up errors
-            }
-            _ => {}
-        };
+        // An aborted core can't be called anymore, and its save data may come from half a frame
+        if self.guest.aborted {
+            return;
+        }
+
+        // Don't lose the save data that changed since the last flush
+        let _ = self.flush_save_data();
+
+        // There is nothing left to do if the core fails here
+        let _ = self.guest.call(
+            &mut self.timeout,
+            self.guest.wasm_free_emulator,
+            self.guest.emulator_pointer,
+        );
     }
 }
 
-struct WasmVec {
-    pub ptr: u32,
-    pub length: u32,
+/// A copy of the guest state, taken without the core's cooperation.
+///
+/// Only the linear memory and the exported mutable globals are captured, and tables are not. This
+/// is only valid for cores whose sole non-exported mutable global is the stack pointer, and that
+/// never change their tables at runtime, which is what Rust cores built with `#[rustretro_plugin]`
+/// look like. Between two calls into the core the shadow stack is fully unwound, so the stack
+/// pointer always holds the same value and can be skipped.
+///
+/// For any other core, restoring a snapshot leaves the instance in an inconsistent state without
+/// any error. Those cores should implement `save_state` instead.
+///
+/// A snapshot only fits the exact build of the core it was taken from, so `restore` rejects it in
+/// a runner of any other core. A runner loaded from a precompiled artifact only accepts the
+/// snapshots of runners loaded from the same artifact.
+#[derive(Clone)]
+pub struct Snapshot {
+    core_digest: [u8; 32],
+    emulator_pointer: u32,
+    memory: Vec<u8>,
+    globals: Vec<(String, Val)>,
 }
 
-#[cfg(debug_assertions)]
-impl Drop for WasmVec {
-    fn drop(&mut self) {
-        // Detect if a vector hasn't been freed properly
-        panic!("A WASM vec wasn't dropped!");
+impl Runner {
+    /// Load a core and create the emulator. Fails with the core's message if it rejects the ROM.
+    pub fn new(
+        core: &[u8],
+        rom: &[u8],
+        save_data: Option<&[u8]>,
+        timeout_ms: u64,
+    ) -> Result<Self, RunnerError> {
+        Self::with_limits(core, rom, save_data, timeout_ms, ResourceLimits::default())
     }
-}
 
-impl Runner {
-    ///
-    pub fn new(core: &[u8], rom: &[u8], timeout_ms: u64) -> Self {
-        // Optimize the engine for execution speed
-        let mut config = Config::new();
-        config.cranelift_opt_level(OptLevel::Speed);
-        config.epoch_interruption(true);
-
-        // Compile the WASM module
-        let engine = Engine::new(&config).expect("wgpu config is invalid!");
-        let module = Module::new(&engine, core).unwrap();
-        let mut store = Store::new(&engine, "Rustretro Wasmtime Runner".to_string());
-        let instance = Instance::new(&mut store, &module, &[]).unwrap();
-
-        // Limit time for the new function
-        store.set_epoch_deadline(timeout_ms);
-
-        // Increment the epoch
-        let (epoch_stop_sender, epoch_stop_receiver) = std::sync::mpsc::channel();
-        let epoch_handle = Some(std::thread::spawn(move || loop {
-            match epoch_stop_receiver.try_recv() {
-                Ok(_) => break,
-                _ => {}
-            };
-
-            std::thread::sleep(Duration::from_millis(1));
-            engine.increment_epoch();
-        }));
-
-        // The default memory is simply called "memory"
-        let memory = instance.get_memory(&mut store, "memory").unwrap();
-
-        // Bind allocation functions
-        let wasm_alloc_vec = instance
-            .get_typed_func::<u32, u32, _>(&mut store, "__rustretro_plugin_alloc_vec")
-            .unwrap();
-        let wasm_free_vec = instance
-            .get_typed_func::<(u32, u32), (), _>(&mut store, "__rustretro_plugin_free_vec")
-            .unwrap();
-
-        // Copy the rom to WASM memory
-        let rom_buffer = alloc_vec_static(&mut store, &wasm_alloc_vec, rom.len() as u32);
-
-        memory
-            .write(&mut store, rom_buffer.ptr as usize, rom)
-            .unwrap();
-
-        // Instanciate the emulator
-        let wasm_create_core = instance
-            .get_typed_func::<(u32, u32), u32, _>(&mut store, "__rustretro_plugin_create_core")
-            .unwrap();
-
-        let emulator_pointer = wasm_create_core
-            .call(&mut store, (rom_buffer.ptr, rom_buffer.length))
-            .unwrap();
-
-        // Free the ROM buffer
-        free_vec_static(&mut store, &wasm_free_vec, rom_buffer);
-
-        // Fetch the core metadata
-        let wasm_get_metadata = instance
-            .get_typed_func::<u32, u64, _>(&mut store, "__rustretro_plugin_get_metadata")
-            .unwrap();
-
-        let ptr = wasm_get_metadata
-            .call(&mut store, emulator_pointer)
-            .unwrap();
-
-        let metadata_buffer = expand_return_pointer(ptr);
-
-        let mut metadata_bytes = vec![0u8; metadata_buffer.length as usize];
-        memory
-            .read(
-                &mut store,
-                metadata_buffer.ptr as usize,
-                &mut metadata_bytes,
-            )
-            .unwrap();
-
-        let metadata: Metadata = serde_json::from_slice(&metadata_bytes).unwrap();
-
-        // Free the metadata buffer
-        free_vec_static(&mut store, &wasm_free_vec, metadata_buffer);
-
-        // Bind exposed functions
-        let wasm_controller_input = instance
-            .get_typed_func::<(u32, u32), (), _>(&mut store, "__rustretro_plugin_controller_input")
-            .unwrap();
-        let wasm_clock_until_frame = instance
-            .get_typed_func::<u32, u64, _>(&mut store, "__rustretro_plugin_clock_until_frame")
-            .unwrap();
-
-        let wasm_free_emulator = instance
-            .get_typed_func::<u32, (), _>(&mut store, "__rustretro_plugin_free_emulator")
-            .unwrap();
-
-        Self {
-            emulator_pointer,
-
-            timeout_ms,
-            epoch_handle,
-            epoch_stop_sender,
-
-            store,
-            memory,
+    /// Same as `new`, with custom caps on the memory and tables the core can allocate
+    pub fn with_limits(
+        core: &[u8],
+        rom: &[u8],
+        save_data: Option<&[u8]>,
+        timeout_ms: u64,
+        limits: ResourceLimits,
+    ) -> Result<Self, RunnerError> {
+        Self::with_runtime(&Runtime::new(), core, rom, save_data, timeout_ms, limits)
+    }
+
+    /// Meter the core with fuel instead of wall-clock time, so a call is interrupted at the
+    /// exact same point on every machine
+    pub fn with_fuel(
+        core: &[u8],
+        rom: &[u8],
+        save_data: Option<&[u8]>,
+        budget: FuelBudget,
+        limits: ResourceLimits,
+    ) -> Result<Self, RunnerError> {
+        Self::with_runtime_and_fuel(&Runtime::with_fuel(), core, rom, save_data, budget, limits)
+    }
+
+    /// Same as `with_limits`, in a runtime shared with other runners
+    pub fn with_runtime(
+        runtime: &Runtime,
+        core: &[u8],
+        rom: &[u8],
+        save_data: Option<&[u8]>,
+        timeout_ms: u64,
+        limits: ResourceLimits,
+    ) -> Result<Self, RunnerError> {
+        let timeout = Timeout::new(runtime, TimeoutStrategy::Epoch(timeout_ms))?;
+        let module = runtime.module(core)?;
+
+        Self::create(
+            runtime,
+            module,
+            CoreSource::Wasm(core),
+            rom,
+            save_data,
+            timeout,
+            limits,
+        )
+    }
+
+    /// Same as `with_fuel`, in a runtime shared with other runners
+    pub fn with_runtime_and_fuel(
+        runtime: &Runtime,
+        core: &[u8],
+        rom: &[u8],
+        save_data: Option<&[u8]>,
+        budget: FuelBudget,
+        limits: ResourceLimits,
+    ) -> Result<Self, RunnerError> {
+        let timeout = Timeout::new(runtime, TimeoutStrategy::Fuel(budget))?;
+        let module = runtime.module(core)?;
+
+        Self::create(
+            runtime,
+            module,
+            CoreSource::Wasm(core),
+            rom,
+            save_data,
+            timeout,
+            limits,
+        )
+    }
+
+    /// `source` is what the module was compiled or deserialized from
+    fn create(
+        runtime: &Runtime,
+        module: Module,
+        source: CoreSource,
+        rom: &[u8],
+        save_data: Option<&[u8]>,
+        mut timeout: Timeout,
+        limits: ResourceLimits,
+    ) -> Result<Self, RunnerError> {
+        // Check the whole ABI at once, rather than failing on the first missing export
+        let (report, core_digest) = match source {
+            CoreSource::Wasm(core) => (
+                ValidationReport::for_core(&module, core),
+                Sha256::digest(core).into(),
+            ),
+            // The version section isn't kept in the artifact
+            CoreSource::Precompiled(artifact) => (
+                ValidationReport::new(&module),
+                Sha256::digest(artifact).into(),
+            ),
+        };
+        if !report.is_valid() {
+            return Err(RunnerError::InvalidCore(report));
+        }
+
+        let guest = Guest::new(
+            runtime.engine(),
+            &module,
+            rom,
+            save_data,
+            &mut timeout,
+            limits,
+        )?;
+
+        Ok(Self {
+            runtime: runtime.clone(),
+            module,
+            core_digest,
+            rom: rom.to_vec(),
+            limits,
+
+            timeout,
+
+            save_data: SaveDataTracker::new(save_data),
+
+            guest,
+        })
+    }
+
+    /// Set the function that decides what happens when a call into the core runs past its timeout.
+    /// It receives the time spent in the call so far. Without it, the call is aborted.
+    pub fn on_timeout(&mut self, handler: impl FnMut(Duration) -> TimeoutAction + Send + 'static) {
+        self.timeout.handler = Some(Box::new(handler));
+    }
+
+    /// Change the fuel given to the next calls. This has no effect on a runner created with a timeout.
+    pub fn set_fuel_budget(&mut self, budget: FuelBudget) {
+        if self.timeout.fuel.is_some() {
+            self.timeout.fuel = Some(budget);
+        }
+    }
+
+    /// Fuel burned by the core since it was loaded, or `None` if it isn't metered with fuel
+    pub fn fuel_consumed(&self) -> Option<u64> {
+        self.timeout.fuel.map(|_| self.guest.fuel_consumed)
+    }
+
+    /// Recreate the emulator in a fresh instance of the core, starting from the last save data
+    /// fetched. This is how to recover from an aborted call.
+    pub fn reload(&mut self) -> Result<(), RunnerError> {
+        self.guest = Guest::new(
+            self.runtime.engine(),
+            &self.module,
+            &self.rom,
+            self.save_data.last(),
+            &mut self.timeout,
+            self.limits,
+        )?;
+
+        Ok(())
+    }
+
+    /// Input on a port the core doesn't have is ignored, like an unplugged controller
+    pub fn controller_input(&mut self, port: u32, input: &DeviceInput) -> Result<(), RunnerError> {
+        if port as usize >= self.guest.metadata.ports.len() {
+            return Ok(());
+        }
+
+        let input = serde_json::to_vec(input).expect("the input should always serialize");
+        let input_buffer = self.guest.write_vec(&mut self.timeout, &input)?;
+
+        if let Err(e) = self.guest.call(
+            &mut self.timeout,
+            self.guest.wasm_controller_input,
+            (
+                self.guest.emulator_pointer,
+                port,
+                input_buffer.ptr,
+                input_buffer.length,
+            ),
+        ) {
+            input_buffer.leak();
+            return Err(e);
+        }
+
+        self.guest.free_vec(&mut self.timeout, input_buffer)
+    }
+
+    /// Events on a port without a keyboard are ignored
+    pub fn keyboard_input(&mut self, port: u32, event: KeyboardEvent) -> Result<(), RunnerError> {
+        let device = self.guest.metadata.ports.get(port as usize);
+
+        if !self.capabilities().contains(Capabilities::KEYBOARD)
+            || device.map(|device| device.device_type) != Some(DeviceType::Keyboard)
+        {
+            return Ok(());
+        }
+
+        self.guest.call(
+            &mut self.timeout,
+            self.guest.wasm_keyboard_input,
+            (
+                self.guest.emulator_pointer,
+                port,
+                event.keycode as u32,
+                event.pressed as u32,
+            ),
+        )
+    }
+
+    /// Reset the console, keeping the save data
+    pub fn reset(&mut self, kind: ResetKind) -> Result<(), RunnerError> {
+        if !self.capabilities().contains(Capabilities::RESET) {
+            return Err(RunnerError::Unsupported(Capabilities::RESET));
+        }
+
+        self.guest.call(
+            &mut self.timeout,
+            self.guest.wasm_reset,
+            (self.guest.emulator_pointer, kind.bits()),
+        )
+    }
+
+    pub fn clock_until_frame(&mut self) -> Result<Vec<u8>, RunnerError> {
+        let metadata = &self.guest.metadata;
+        let max_length = (metadata.width as usize)
+            .saturating_mul(metadata.height as usize)
+            .saturating_mul(metadata.pixel_format.bytes_per_pixel() as usize);
+
+        let fuel = self.timeout.fuel.map(|budget| budget.clock_until_frame);
+        let ptr = self.guest.call_with_fuel(
+            &mut self.timeout,
+            self.guest.wasm_clock_until_frame,
+            self.guest.emulator_pointer,
+            fuel,
+        )?;
+
+        self.guest
+            .read_vec(&mut self.timeout, expand_return_pointer(ptr), max_length)
+    }
+
+    /// Fetch the interleaved PCM samples generated during the last frame
+    pub fn audio_samples(&mut self) -> Result<Vec<i16>, RunnerError> {
+        if !self.capabilities().contains(Capabilities::AUDIO) {
+            return Ok(Vec::new());
+        }
+
+        // A frame can't hold more than a second of audio
+        let metadata = &self.guest.metadata;
+        let max_length = (metadata.audio_sample_rate as usize)
+            .saturating_mul(metadata.audio_channels as usize)
+            .saturating_mul(std::mem::size_of::<i16>());
+
+        Ok(self
+            .guest
+            .fetch_vec(&mut self.timeout, self.guest.wasm_audio_samples, max_length)?
+            .chunks_exact(2)
+            .map(|s| i16::from_le_bytes([s[0], s[1]]))
+            .collect())
+    }
 
-            metadata,
+    /// Fetch the battery-backed RAM, only if it changed since the last time it was fetched
+    pub fn dirty_save_data(&mut self) -> Result<Option<Vec<u8>>, RunnerError> {
+        let data = self.fetch_save_data()?;
+        Ok(self.save_data.update(data))
+    }
+
+    /// Set the function that receives the save data when it is flushed
+    pub fn on_save_data(&mut self, handler: impl FnMut(&[u8]) + Send + 'static) {
+        self.save_data.set_handler(handler);
+    }
+
+    /// Send the save data to the handler if it changed since the last flush.
+    /// This is also done when the runner is dropped.
+    pub fn flush_save_data(&mut self) -> Result<(), RunnerError> {
+        if self.save_data.has_handler() {
+            let data = self.fetch_save_data()?;
+            self.save_data.flush(data);
+        }
+
+        Ok(())
+    }
+
+    fn fetch_save_data(&mut self) -> Result<Option<Vec<u8>>, RunnerError> {
+        if !self.capabilities().contains(Capabilities::SAVE_DATA) {
+            return Ok(None);
+        }
+
+        // The save data is only bounded by the guest memory
+        let data =
+            self.guest
+                .fetch_vec(&mut self.timeout, self.guest.wasm_save_data, usize::MAX)?;
+        Ok(Some(data))
+    }
+
+    /// Serialize the emulator state
+    pub fn save_state(&mut self) -> Result<Vec<u8>, RunnerError> {
+        if !self.capabilities().contains(Capabilities::SAVE_STATE) {
+            return Err(RunnerError::Unsupported(Capabilities::SAVE_STATE));
+        }
+
+        // The state is only bounded by the guest memory
+        self.guest
+            .fetch_vec(&mut self.timeout, self.guest.wasm_save_state, usize::MAX)
+    }
 
-            wasm_controller_input,
-            wasm_clock_until_frame,
-            _wasm_alloc_vec: wasm_alloc_vec,
-            wasm_free_vec,
-            wasm_free_emulator,
+    /// Restore a state previously returned by `save_state`
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), RunnerError> {
+        if !self.capabilities().contains(Capabilities::SAVE_STATE) {
+            return Err(RunnerError::Unsupported(Capabilities::SAVE_STATE));
         }
+
+        let state_buffer = self.guest.write_vec(&mut self.timeout, state)?;
+
+        if let Err(e) = self.guest.call(
+            &mut self.timeout,
+            self.guest.wasm_load_state,
+            (
+                self.guest.emulator_pointer,
+                state_buffer.ptr,
+                state_buffer.length,
+            ),
+        ) {
+            state_buffer.leak();
+            return Err(e);
+        }
+
+        self.guest.free_vec(&mut self.timeout, state_buffer)
+    }
+
+    /// Copy `length` bytes of a region of `Metadata::memory_regions`, starting at `offset`
+    pub fn read_memory(
+        &mut self,
+        region: u32,
+        offset: u32,
+        length: u32,
+    ) -> Result<Vec<u8>, RunnerError> {
+        self.check_memory_range(region, offset, length as usize)?;
+
+        let ptr = self.guest.call(
+            &mut self.timeout,
+            self.guest.wasm_read_memory,
+            (self.guest.emulator_pointer, region, offset, length),
+        )?;
+
+        self.guest.read_vec(
+            &mut self.timeout,
+            expand_return_pointer(ptr),
+            length as usize,
+        )
+    }
+
+    /// Overwrite a region of `Metadata::memory_regions`, starting at `offset`
+    pub fn write_memory(
+        &mut self,
+        region: u32,
+        offset: u32,
+        data: &[u8],
+    ) -> Result<(), RunnerError> {
+        self.check_memory_range(region, offset, data.len())?;
+
+        let data_buffer = self.guest.write_vec(&mut self.timeout, data)?;
+
+        if let Err(e) = self.guest.call(
+            &mut self.timeout,
+            self.guest.wasm_write_memory,
+            (
+                self.guest.emulator_pointer,
+                region,
+                offset,
+                data_buffer.ptr,
+                data_buffer.length,
+            ),
+        ) {
+            data_buffer.leak();
+            return Err(e);
+        }
+
+        self.guest.free_vec(&mut self.timeout, data_buffer)
+    }
+
+    /// The core is promised to only get ranges inside the regions it declared
+    fn check_memory_range(
+        &self,
+        region: u32,
+        offset: u32,
+        length: usize,
+    ) -> Result<(), RunnerError> {
+        if !self.capabilities().contains(Capabilities::MEMORY_ACCESS) {
+            return Err(RunnerError::Unsupported(Capabilities::MEMORY_ACCESS));
+        }
+
+        if !is_valid_memory_range(&self.guest.metadata, region, offset, length) {
+            return Err(RunnerError::InvalidMemoryRange {
+                region,
+                offset,
+                length,
+            });
+        }
+
+        Ok(())
     }
 
-    pub fn controller_input(&mut self, input: ControllerInput) {
-        let input = input.bits() as u32;
-        self.wasm_controller_input
-            .call(&mut self.store, (self.emulator_pointer, input))
-            .unwrap();
+    /// Snapshot the linear memory and the globals of the instance
+    pub fn snapshot(&mut self) -> Snapshot {
+        let guest = &mut self.guest;
+        let memory = guest.memory.data(&guest.store).to_vec();
+
+        let exported_globals: Vec<(String, Global)> = guest
+            .instance
+            .exports(&mut guest.store)
+            .filter_map(|export| {
+                let name = export.name().to_string();
+                export.into_global().map(|global| (name, global))
+            })
+            .collect();
+
+        // Immutable globals can't change, so there is no need to save them
+        let globals = exported_globals
+            .into_iter()
+            .filter_map(|(name, global)| {
+                if global.ty(&guest.store).mutability() == Mutability::Var {
+                    Some((name, global.get(&mut guest.store)))
+                } else {
+                    None
+                }
+            })
+            .collect();
+
+        Snapshot {
+            core_digest: self.core_digest,
+            emulator_pointer: guest.emulator_pointer,
+            memory,
+            globals,
+        }
     }
 
-    pub fn clock_until_frame(&mut self) -> Vec<u8> {
-        self.store.set_epoch_deadline(self.timeout_ms);
+    /// Restore a snapshot previously taken from a runner of the same core. This also works after a
+    /// reload. See `Snapshot` for the cores this is valid for.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), RunnerError> {
+        if snapshot.core_digest != self.core_digest {
+            return Err(RunnerError::InvalidSnapshot);
+        }
 
-        let ptr = self
-            .wasm_clock_until_frame
-            .call(&mut self.store, self.emulator_pointer)
-            .unwrap();
+        let guest = &mut self.guest;
 
-        let frame_buffer = expand_return_pointer(ptr);
+        // The memory can only grow, so we zero out anything that is past the snapshot
+        let current_size = guest.memory.data_size(&guest.store);
+        if snapshot.memory.len() > current_size {
+            let missing_pages = (snapshot.memory.len() - current_size) / WASM_PAGE_SIZE;
+            guest
+                .memory
+                .grow(&mut guest.store, missing_pages as u64)
+                .map_err(|_| RunnerError::InvalidSnapshot)?;
+        }
 
-        let mut buffer = vec![0u8; frame_buffer.length as usize];
-        self.memory
-            .read(&mut self.store, frame_buffer.ptr as usize, &mut buffer)
-            .unwrap();
+        let data = guest.memory.data_mut(&mut guest.store);
+        data[..snapshot.memory.len()].copy_from_slice(&snapshot.memory);
+        data[snapshot.memory.len()..].fill(0);
+
+        for (name, value) in &snapshot.globals {
+            guest
+                .instance
+                .get_global(&mut guest.store, name)
+                .ok_or(RunnerError::InvalidSnapshot)?
+                .set(&mut guest.store, value.clone())
+                .map_err(|_| RunnerError::InvalidSnapshot)?;
+        }
 
-        self.free_vec(frame_buffer);
+        guest.emulator_pointer = snapshot.emulator_pointer;
+        // The whole state was replaced, so an aborted call doesn't matter anymore
+        guest.aborted = false;
 
-        buffer
+        Ok(())
     }
 
     pub fn get_metadata(&self) -> &Metadata {
-        &self.metadata
+        &self.guest.metadata
     }
 
-    fn free_vec(&mut self, wasm_vec: WasmVec) {
-        free_vec_static(&mut self.store, &self.wasm_free_vec, wasm_vec)
+    /// The optional features the core advertised
+    pub fn capabilities(&self) -> Capabilities {
+        self.guest.capabilities
     }
 }
 
-fn alloc_vec_static(
-    store: &mut Store<String>,
-    wasm_alloc_vec: &TypedFunc<u32, u32>,
-    length: u32,
-) -> WasmVec {
-    let ptr = wasm_alloc_vec.call(store, length).unwrap();
+impl CoreRuntime for Runner {
+    type Error = RunnerError;
 
-    WasmVec { ptr, length }
-}
+    fn get_metadata(&self) -> &Metadata {
+        Runner::get_metadata(self)
+    }
 
-fn free_vec_static(
-    store: &mut Store<String>,
-    wasm_free_vec: &TypedFunc<(u32, u32), ()>,
-    wasm_vec: WasmVec,
-) {
-    wasm_free_vec
-        .call(store, (wasm_vec.ptr, wasm_vec.length))
-        .unwrap();
+    fn controller_input(&mut self, port: u32, input: &DeviceInput) -> Result<(), RunnerError> {
+        Runner::controller_input(self, port, input)
+    }
 
-    std::mem::forget(wasm_vec);
-}
+    fn clock_until_frame(&mut self) -> Result<Vec<u8>, RunnerError> {
+        Runner::clock_until_frame(self)
+    }
+
+    fn keyboard_input(&mut self, port: u32, event: KeyboardEvent) -> Result<(), RunnerError> {
+        Runner::keyboard_input(self, port, event)
+    }
+
+    fn reset(&mut self, kind: ResetKind) -> Result<(), RunnerError> {
+        Runner::reset(self, kind)
+    }
+
+    fn audio_samples(&mut self) -> Result<Vec<i16>, RunnerError> {
+        Runner::audio_samples(self)
+    }
+
+    fn flush_save_data(&mut self) -> Result<(), RunnerError> {
+        Runner::flush_save_data(self)
+    }
+
+    fn save_state(&mut self) -> Result<Vec<u8>, RunnerError> {
+        Runner::save_state(self)
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> Result<(), RunnerError> {
+        Runner::load_state(self, state)
+    }
+
+    fn read_memory(
+        &mut self,
+        region: u32,
+        offset: u32,
+        length: u32,
+    ) -> Result<Vec<u8>, RunnerError> {
+        Runner::read_memory(self, region, offset, length)
+    }
+
+    fn write_memory(&mut self, region: u32, offset: u32, data: &[u8]) -> Result<(), RunnerError> {
+        Runner::write_memory(self, region, offset, data)
+    }
+
+    fn reload(&mut self) -> Result<(), RunnerError> {
+        Runner::reload(self)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Runner::capabilities(self)
+    }
 
-/// Returning tuples is not well supported yet, so we return a u64 and bitmask/shift to split in into two
-fn expand_return_pointer(ptr: u64) -> WasmVec {
-    WasmVec {
-        ptr: (ptr & 0xFFFFFFFF) as u32,
-        length: (ptr >> 32) as u32,
+    /// Only an aborted call is worth a reload, any other error would happen again
+    fn is_recoverable(&self, error: &RunnerError) -> bool {
+        matches!(
+            error,
+            RunnerError::Timeout | RunnerError::OutOfFuel | RunnerError::Aborted
+        )
     }
 }
@@ -0,0 +1,66 @@
+use wasmtime::ResourceLimiter;
+
+use crate::RunnerError;
+
+/// Caps on what a core can allocate, checked every time it grows a memory or a table
+#[derive(Clone, Copy, Debug)]
+pub struct ResourceLimits {
+    /// Maximum size of a linear memory, in bytes
+    pub memory_size: usize,
+    /// Maximum number of elements in a table
+    pub table_elements: u32,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            memory_size: 256 * 1024 * 1024,
+            table_elements: 10_000,
+        }
+    }
+}
+
+/// The data of the store. Remembers the last limit that was hit, so a trap can be explained.
+pub(crate) struct Limiter {
+    limits: ResourceLimits,
+    exceeded: Option<RunnerError>,
+}
+
+impl Limiter {
+    pub fn new(limits: ResourceLimits) -> Self {
+        Self {
+            limits,
+            exceeded: None,
+        }
+    }
+
+    pub fn take_exceeded(&mut self) -> Option<RunnerError> {
+        self.exceeded.take()
+    }
+}
+
+impl ResourceLimiter for Limiter {
+    fn memory_growing(&mut self, _current: usize, desired: usize, _maximum: Option<usize>) -> bool {
+        if desired > self.limits.memory_size {
+            self.exceeded = Some(RunnerError::MemoryLimit {
+                desired,
+                limit: self.limits.memory_size,
+            });
+            return false;
+        }
+
+        true
+    }
+
+    fn table_growing(&mut self, _current: u32, desired: u32, _maximum: Option<u32>) -> bool {
+        if desired > self.limits.table_elements {
+            self.exceeded = Some(RunnerError::TableLimit {
+                desired,
+                limit: self.limits.table_elements,
+            });
+            return false;
+        }
+
+        true
+    }
+}
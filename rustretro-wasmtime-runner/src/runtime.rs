@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use sha2::{Digest, Sha256};
+use wasmtime::*;
+
+use crate::timeout::EpochTicker;
+use crate::{RunnerError, ValidationReport};
+
+/// An engine shared by many runners. Each core is compiled once, and a single thread drives the
+/// epoch of every runner using it.
+///
+/// Cloning it is cheap, and the ticker stops once the last clone and runner are dropped.
+///
+/// Compiled cores are also cached on disk, so the next launch doesn't have to compile them again.
+#[derive(Clone)]
+pub struct Runtime {
+    inner: Arc<RuntimeInner>,
+    cache_dir: Option<PathBuf>,
+}
+
+/// How the engine compiles and runs the cores
+#[derive(Clone, Debug)]
+pub struct EngineOptions {
+    pub opt_level: OptLevel,
+    /// Meter the cores with fuel instead of interrupting them after a wall-clock timeout
+    pub fuel: bool,
+    pub simd: bool,
+    /// Reference types depend on it, so they are disabled along with it
+    pub bulk_memory: bool,
+    pub threads: bool,
+    /// Emit DWARF so native debuggers can step through the cores
+    pub debug_info: bool,
+}
+
+impl Default for EngineOptions {
+    fn default() -> Self {
+        Self {
+            opt_level: OptLevel::Speed,
+            fuel: false,
+            simd: true,
+            bulk_memory: true,
+            threads: false,
+            debug_info: false,
+        }
+    }
+}
+
+struct RuntimeInner {
+    engine: Engine,
+    options: EngineOptions,
+    modules: Mutex<HashMap<Vec<u8>, Module>>,
+    _epoch_ticker: Option<EpochTicker>,
+}
+
+impl Runtime {
+    /// Runners of this runtime are interrupted after a wall-clock timeout
+    pub fn new() -> Self {
+        Self::with_options(EngineOptions::default())
+            .expect("the default engine options should be valid")
+    }
+
+    /// Runners of this runtime are metered with fuel, see `Runner::with_fuel`
+    pub fn with_fuel() -> Self {
+        let options = EngineOptions {
+            fuel: true,
+            ..EngineOptions::default()
+        };
+
+        Self::with_options(options).expect("the default engine options should be valid")
+    }
+
+    /// Fails if the engine doesn't support this combination of options
+    pub fn with_options(options: EngineOptions) -> Result<Self, RunnerError> {
+        let mut config = Config::new();
+        config.cranelift_opt_level(options.opt_level.clone());
+        config.epoch_interruption(!options.fuel);
+        config.consume_fuel(options.fuel);
+
+        config.wasm_simd(options.simd);
+        config.wasm_bulk_memory(options.bulk_memory);
+        config.wasm_reference_types(options.bulk_memory);
+        config.wasm_threads(options.threads);
+        config.debug_info(options.debug_info);
+
+        // Calls are async so a core past its deadline can yield instead of trapping
+        config.async_support(true);
+
+        let engine = Engine::new(&config).map_err(RunnerError::InvalidConfig)?;
+
+        // Increment the epoch, unless the fuel is what interrupts the cores
+        let epoch_ticker = (!options.fuel).then(|| EpochTicker::start(engine.clone()));
+
+        Ok(Self {
+            inner: Arc::new(RuntimeInner {
+                engine,
+                options,
+                modules: Mutex::new(HashMap::new()),
+                _epoch_ticker: epoch_ticker,
+            }),
+            cache_dir: dirs_next::cache_dir().map(|dir| dir.join("rustretro")),
+        })
+    }
+
+    /// Store the compiled cores in this directory instead of the user's cache directory
+    pub fn with_cache_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(dir.into());
+        self
+    }
+
+    /// Always compile the cores, without touching the disk
+    pub fn without_cache(mut self) -> Self {
+        self.cache_dir = None;
+        self
+    }
+
+    pub fn uses_fuel(&self) -> bool {
+        self.inner.options.fuel
+    }
+
+    pub(crate) fn engine(&self) -> &Engine {
+        &self.inner.engine
+    }
+
+    /// Compile the core, or reuse it if it was already compiled by this runtime or found in the cache
+    pub(crate) fn module(&self, core: &[u8]) -> Result<Module, RunnerError> {
+        let mut modules = self.inner.modules.lock().unwrap();
+
+        if let Some(module) = modules.get(core) {
+            return Ok(module.clone());
+        }
+
+        let module = match self.load_cached(core) {
+            Some(module) => module,
+            None => {
+                let module = Module::new(&self.inner.engine, core).map_err(RunnerError::Compile)?;
+
+                // The cache is only an optimization, failing to fill it isn't an error
+                if let Ok(artifact) = module.serialize() {
+                    let _ = self.store_cached(core, &artifact);
+                }
+
+                module
+            }
+        };
+
+        modules.insert(core.to_vec(), module.clone());
+
+        Ok(module)
+    }
+
+    /// Check that a core follows the ABI, without instantiating it
+    pub fn validate(&self, core: &[u8]) -> Result<ValidationReport, RunnerError> {
+        Ok(ValidationReport::for_core(&self.module(core)?, core))
+    }
+
+    /// Compile a core ahead of time and return the native artifact, to be loaded with
+    /// `RunnerBuilder::build_precompiled`. It is also written to the cache, so runners of this
+    /// runtime start without compiling it.
+    pub fn precompile(&self, core: &[u8]) -> Result<Vec<u8>, RunnerError> {
+        let artifact = self
+            .inner
+            .engine
+            .precompile_module(core)
+            .map_err(RunnerError::Compile)?;
+
+        if self.cache_dir.is_some() {
+            self.store_cached(core, &artifact)
+                .map_err(|e| RunnerError::Cache(e.into()))?;
+        }
+
+        Ok(artifact)
+    }
+
+    /// Load an artifact returned by `precompile`.
+    ///
+    /// # Safety
+    ///
+    /// See `RunnerBuilder::build_precompiled`.
+    pub(crate) unsafe fn deserialize(&self, artifact: &[u8]) -> Result<Module, RunnerError> {
+        Module::deserialize(&self.inner.engine, artifact).map_err(RunnerError::InvalidArtifact)
+    }
+
+    /// Where the artifact of this core is cached, if the cache is enabled.
+    /// It is named after the SHA-256 of the core and of the engine configuration.
+    pub fn cache_path(&self, core: &[u8]) -> Option<PathBuf> {
+        let options = format!("{:?}", self.inner.options);
+
+        // Each part is prefixed with its length, so two different sets of parts never hash the same
+        let mut hasher = Sha256::new();
+        for part in [
+            env!("CARGO_PKG_VERSION").as_bytes(),
+            options.as_bytes(),
+            core,
+        ] {
+            hasher.update((part.len() as u64).to_le_bytes());
+            hasher.update(part);
+        }
+
+        let file_name = format!("{:x}.cwasm", hasher.finalize());
+        self.cache_dir.as_ref().map(|dir| dir.join(file_name))
+    }
+
+    fn load_cached(&self, core: &[u8]) -> Option<Module> {
+        let path = self.cache_path(core)?;
+
+        // SAFETY: nothing proves the file was written by `store_cached`: it is native code loaded
+        // from the cache directory, so whoever can write there can run code in the frontend, like
+        // with any executable of the user. Wasmtime rejects artifacts from another version or
+        // configuration, and the name is the digest of the core, so the artifact of another core
+        // is never picked up by accident.
+        unsafe { Module::deserialize_file(&self.inner.engine, path) }.ok()
+    }
+
+    fn store_cached(&self, core: &[u8], artifact: &[u8]) -> std::io::Result<()> {
+        let path = match self.cache_path(core) {
+            Some(x) => x,
+            None => return Ok(()),
+        };
+
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+
+        // Write to a temporary file first, so another process never loads a partial artifact
+        let temp_path = temporary_path(&path);
+        std::fs::write(&temp_path, artifact)?;
+        std::fs::rename(&temp_path, &path)
+    }
+}
+
+fn temporary_path(path: &Path) -> PathBuf {
+    path.with_extension(format!("{}.tmp", std::process::id()))
+}
+
+impl Default for Runtime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
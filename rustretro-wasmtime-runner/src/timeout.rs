@@ -0,0 +1,117 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use rustretro_runner_common::FuelBudget;
+use wasmtime::Engine;
+
+use crate::{RunnerError, Runtime, TimeoutStrategy};
+
+/// What to do with a call into the core that ran past its timeout
+pub enum TimeoutAction {
+    /// Let the core run for this many more milliseconds
+    Extend(u64),
+    /// Cancel the call. The core can be left in an inconsistent state, so it should be reloaded
+    Abort,
+}
+
+/// Receives the time spent in the call so far
+pub(crate) type TimeoutHandler = Box<dyn FnMut(Duration) -> TimeoutAction + Send>;
+
+pub(crate) struct Timeout {
+    pub ms: u64,
+    pub handler: Option<TimeoutHandler>,
+    /// Replaces the epoch deadline when set
+    pub fuel: Option<FuelBudget>,
+}
+
+impl Timeout {
+    /// Fails if the runtime doesn't meter the cores the way the strategy asks for
+    pub fn new(runtime: &Runtime, strategy: TimeoutStrategy) -> Result<Self, RunnerError> {
+        match strategy {
+            TimeoutStrategy::Epoch(ms) if !runtime.uses_fuel() => Ok(Self {
+                ms,
+                handler: None,
+                fuel: None,
+            }),
+            TimeoutStrategy::Fuel(budget) if runtime.uses_fuel() => Ok(Self {
+                ms: 0,
+                handler: None,
+                fuel: Some(budget),
+            }),
+            _ => Err(RunnerError::IncompatibleRuntime),
+        }
+    }
+
+    /// Drive a call into the core to completion.
+    ///
+    /// Once the core is past its deadline, it yields back to us on every epoch tick. This is where the
+    /// handler decides if the call goes on or gets dropped. Without a handler, the call is aborted.
+    pub fn run<T>(&mut self, call: impl Future<Output = T>) -> Result<T, RunnerError> {
+        let waker = futures::task::noop_waker();
+        let mut context = Context::from_waker(&waker);
+        futures::pin_mut!(call);
+
+        let start = Instant::now();
+        let mut extended_until: Option<Instant> = None;
+
+        loop {
+            match call.as_mut().poll(&mut context) {
+                Poll::Ready(x) => return Ok(x),
+                Poll::Pending => {
+                    let now = Instant::now();
+                    if matches!(extended_until, Some(x) if now < x) {
+                        continue;
+                    }
+
+                    let action = match self.handler.as_mut() {
+                        Some(handler) => handler(now - start),
+                        None => TimeoutAction::Abort,
+                    };
+
+                    match action {
+                        TimeoutAction::Extend(ms) => {
+                            extended_until = Some(now + Duration::from_millis(ms))
+                        }
+                        TimeoutAction::Abort => return Err(RunnerError::Timeout),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Increments the epoch of an engine every millisecond until dropped
+pub(crate) struct EpochTicker {
+    handle: Option<JoinHandle<()>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl EpochTicker {
+    pub fn start(engine: Engine) -> Self {
+        // A flag rather than a channel, so the ticker can be shared between threads
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let handle = Some(std::thread::spawn(move || {
+            while !thread_stop.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(1));
+                engine.increment_epoch();
+            }
+        }));
+
+        Self { handle, stop }
+    }
+}
+
+impl Drop for EpochTicker {
+    fn drop(&mut self) {
+        // Stop the epoch
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(x) = self.handle.take() {
+            x.join().unwrap(); // Unwrap to bubble up errors
+        }
+    }
+}
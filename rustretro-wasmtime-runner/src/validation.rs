@@ -0,0 +1,199 @@
+use std::fmt;
+
+use rustretro_plugin::ABI_VERSION;
+use rustretro_runner_common::read_abi_version;
+use wasmtime::*;
+
+use ValType::{I32, I64};
+
+/// Functions every core has to export, with their parameters and results
+const REQUIRED_FUNCTIONS: &[(&str, &[ValType], &[ValType])] = &[
+    ("__rustretro_plugin_abi_version", &[], &[I32]),
+    ("__rustretro_plugin_capabilities", &[], &[I32]),
+    ("__rustretro_plugin_alloc_vec", &[I32], &[I32]),
+    ("__rustretro_plugin_free_vec", &[I32, I32], &[]),
+    (
+        "__rustretro_plugin_create_core",
+        &[I32, I32, I32, I32],
+        &[I32],
+    ),
+    ("__rustretro_plugin_take_error", &[], &[I64]),
+    ("__rustretro_plugin_get_metadata", &[I32], &[I64]),
+    (
+        "__rustretro_plugin_controller_input",
+        &[I32, I32, I32, I32],
+        &[],
+    ),
+    (
+        "__rustretro_plugin_keyboard_input",
+        &[I32, I32, I32, I32],
+        &[],
+    ),
+    ("__rustretro_plugin_clock_until_frame", &[I32], &[I64]),
+    ("__rustretro_plugin_reset", &[I32, I32], &[]),
+    ("__rustretro_plugin_audio_samples", &[I32], &[I64]),
+    ("__rustretro_plugin_save_data", &[I32], &[I64]),
+    ("__rustretro_plugin_save_state", &[I32], &[I64]),
+    ("__rustretro_plugin_load_state", &[I32, I32, I32], &[]),
+    (
+        "__rustretro_plugin_read_memory",
+        &[I32, I32, I32, I32],
+        &[I64],
+    ),
+    (
+        "__rustretro_plugin_write_memory",
+        &[I32, I32, I32, I32, I32],
+        &[],
+    ),
+    ("__rustretro_plugin_free_emulator", &[I32], &[]),
+];
+
+/// An export that doesn't have the type the ABI expects
+#[derive(Clone, Debug)]
+pub struct MistypedExport {
+    pub name: String,
+    pub expected: String,
+    pub found: String,
+}
+
+/// Everything that prevents a module from being used as a core, found without instantiating it
+#[derive(Clone, Debug)]
+pub struct ValidationReport {
+    /// The version of the ABI the core was checked against
+    pub host_abi_version: u32,
+    /// The version of the ABI the core was built for, if it says so in its custom section
+    pub core_abi_version: Option<u32>,
+    pub missing_exports: Vec<String>,
+    pub mistyped_exports: Vec<MistypedExport>,
+    /// The runner doesn't provide any import, so a core can't have any
+    pub unexpected_imports: Vec<String>,
+}
+
+impl ValidationReport {
+    /// Check the exports and imports of a compiled core. Its ABI version is only in the WebAssembly
+    /// bytes, see `for_core`.
+    pub fn new(module: &Module) -> Self {
+        let mut report = Self {
+            host_abi_version: ABI_VERSION,
+            core_abi_version: None,
+            missing_exports: Vec::new(),
+            mistyped_exports: Vec::new(),
+            unexpected_imports: Vec::new(),
+        };
+
+        match module.get_export("memory") {
+            Some(ExternType::Memory(_)) => {}
+            Some(ty) => report.mistyped_exports.push(MistypedExport {
+                name: "memory".to_string(),
+                expected: "memory".to_string(),
+                found: describe_extern(&ty),
+            }),
+            None => report.missing_exports.push("memory".to_string()),
+        }
+
+        for (name, params, results) in REQUIRED_FUNCTIONS {
+            let expected = FuncType::new(params.iter().cloned(), results.iter().cloned());
+
+            match module.get_export(name) {
+                Some(ExternType::Func(ty)) if ty == expected => {}
+                Some(ty) => report.mistyped_exports.push(MistypedExport {
+                    name: name.to_string(),
+                    expected: describe_func(&expected),
+                    found: describe_extern(&ty),
+                }),
+                None => report.missing_exports.push(name.to_string()),
+            }
+        }
+
+        for import in module.imports() {
+            report.unexpected_imports.push(format!(
+                "{}::{}",
+                import.module(),
+                import.name().unwrap_or_default()
+            ));
+        }
+
+        report
+    }
+
+    /// Same as `new`, also reading the ABI version from the WebAssembly bytes of the core
+    pub fn for_core(module: &Module, core: &[u8]) -> Self {
+        let mut report = Self::new(module);
+
+        report.core_abi_version = read_abi_version(core);
+
+        report
+    }
+
+    pub fn is_valid(&self) -> bool {
+        let other_version =
+            matches!(self.core_abi_version, Some(version) if version != self.host_abi_version);
+
+        !other_version
+            && self.missing_exports.is_empty()
+            && self.mistyped_exports.is_empty()
+            && self.unexpected_imports.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_valid() {
+            return write!(
+                f,
+                "the core follows version {} of the ABI",
+                self.host_abi_version
+            );
+        }
+
+        write!(
+            f,
+            "the core doesn't follow version {} of the ABI:",
+            self.host_abi_version
+        )?;
+
+        match self.core_abi_version {
+            Some(version) if version != self.host_abi_version => {
+                write!(f, "\n  it was built for version {}", version)?
+            }
+            Some(_) => {}
+            None => write!(f, "\n  it doesn't say which version it was built for")?,
+        }
+
+        for name in &self.missing_exports {
+            write!(f, "\n  missing export `{}`", name)?;
+        }
+
+        for export in &self.mistyped_exports {
+            write!(
+                f,
+                "\n  `{}` is `{}`, expected `{}`",
+                export.name, export.found, export.expected
+            )?;
+        }
+
+        for name in &self.unexpected_imports {
+            write!(f, "\n  unexpected import `{}`", name)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn describe_func(ty: &FuncType) -> String {
+    let params: Vec<String> = ty.params().map(|x| x.to_string()).collect();
+    let results: Vec<String> = ty.results().map(|x| x.to_string()).collect();
+
+    format!("fn({}) -> ({})", params.join(", "), results.join(", "))
+}
+
+fn describe_extern(ty: &ExternType) -> String {
+    match ty {
+        ExternType::Func(x) => describe_func(x),
+        ExternType::Global(_) => "global".to_string(),
+        ExternType::Table(_) => "table".to_string(),
+        ExternType::Memory(_) => "memory".to_string(),
+        #[allow(unreachable_patterns)]
+        _ => "item".to_string(),
+    }
+}
@@ -1,18 +1,24 @@
-use rustretro_plugin::ControllerInput;
-use rustretro_wasmtime_runner::Runner;
+use rustretro_plugin::{Capabilities, DeviceInput, KeyboardEvent, ResetKind};
+use rustretro_runner_common::CoreRuntime;
 use std::{
     sync::{mpsc, Arc},
     thread::JoinHandle,
     time::{Duration, Instant},
 };
 
+// How often the save data is written back
+const SAVE_DATA_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
 pub enum EmulationMessage {
-    Input(ControllerInput),
+    /// The new state of the device plugged in a port
+    Input(u32, DeviceInput),
+    Key(u32, KeyboardEvent),
+    Reset(ResetKind),
     Stop,
 }
 
 pub fn start(
-    mut emulator: Runner,
+    mut emulator: impl CoreRuntime + Send + 'static,
     queue: Arc<wgpu::Queue>,
     texture: wgpu::Texture,
 ) -> (JoinHandle<()>, mpsc::Sender<EmulationMessage>) {
@@ -23,10 +29,36 @@ pub fn start(
 
         let frame_time = Duration::from_secs_f32(1.0 / metadata.frames_per_seconds);
         let mut last_frame_time = Instant::now();
+        let mut last_flush_time = Instant::now();
 
         loop {
             match input_receiver.try_recv() {
-                Ok(EmulationMessage::Input(x)) => emulator.controller_input(x),
+                Ok(EmulationMessage::Input(port, x)) => {
+                    if let Err(e) = emulator.controller_input(port, &x) {
+                        if !recover(&mut emulator, e) {
+                            break;
+                        }
+                    }
+                }
+                Ok(EmulationMessage::Key(port, event)) => {
+                    if let Err(e) = emulator.keyboard_input(port, event) {
+                        if !recover(&mut emulator, e) {
+                            break;
+                        }
+                    }
+                }
+                Ok(EmulationMessage::Reset(_))
+                    if !emulator.capabilities().contains(Capabilities::RESET) =>
+                {
+                    eprintln!("The core can't be reset");
+                }
+                Ok(EmulationMessage::Reset(kind)) => {
+                    if let Err(e) = emulator.reset(kind) {
+                        if !recover(&mut emulator, e) {
+                            break;
+                        }
+                    }
+                }
                 Ok(EmulationMessage::Stop) => break,
                 _ => {}
             }
@@ -37,7 +69,15 @@ pub fn start(
                 last_frame_time = Instant::now();
 
                 // Get a frame from the emulation and write it to the texture
-                let frame = emulator.clock_until_frame();
+                let frame = match emulator.clock_until_frame() {
+                    Ok(x) => x,
+                    Err(e) => {
+                        if recover(&mut emulator, e) {
+                            continue;
+                        }
+                        break;
+                    }
+                };
 
                 let emulator_width = metadata.width;
                 let emulator_height = metadata.height;
@@ -64,6 +104,19 @@ pub fn start(
                     },
                     texture_size,
                 );
+
+                if last_flush_time + SAVE_DATA_FLUSH_INTERVAL < current_time {
+                    last_flush_time = current_time;
+                    match emulator.flush_save_data() {
+                        Ok(_) => {}
+                        Err(e) if emulator.is_recoverable(&e) => {
+                            if !recover(&mut emulator, e) {
+                                break;
+                            }
+                        }
+                        Err(e) => eprintln!("Could not fetch the save data: {}", e),
+                    }
+                }
             } else {
                 // Sleep for the remaining time until the frame
                 std::thread::sleep(last_frame_time + frame_time - current_time);
@@ -73,3 +126,22 @@ pub fn start(
 
     (join_handle, input_sender)
 }
+
+/// Restart the core from the last save after an aborted call. Returns `false` if the emulation
+/// can't go on.
+fn recover<R: CoreRuntime>(emulator: &mut R, error: R::Error) -> bool {
+    if !emulator.is_recoverable(&error) {
+        eprintln!("The emulator stopped: {}", error);
+        return false;
+    }
+
+    // The core is in an unknown state, so restart it from the last save
+    eprintln!("{}, reloading the core", error);
+    match emulator.reload() {
+        Ok(_) => true,
+        Err(e) => {
+            eprintln!("The emulator stopped: {}", e);
+            false
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+
+use rustretro_plugin::{ControlRole, Device, DeviceType};
+use winit::event::VirtualKeyCode;
+
+/// The keys of the first two players, bound to the buttons with the matching role
+const DEFAULT_KEYS: [&[(ControlRole, VirtualKeyCode)]; 2] = [
+    &[
+        (ControlRole::Up, VirtualKeyCode::Up),
+        (ControlRole::Down, VirtualKeyCode::Down),
+        (ControlRole::Left, VirtualKeyCode::Left),
+        (ControlRole::Right, VirtualKeyCode::Right),
+        (ControlRole::Start, VirtualKeyCode::S),
+        (ControlRole::Select, VirtualKeyCode::A),
+        (ControlRole::FaceSouth, VirtualKeyCode::Z),
+        (ControlRole::FaceEast, VirtualKeyCode::X),
+        (ControlRole::FaceWest, VirtualKeyCode::C),
+        (ControlRole::FaceNorth, VirtualKeyCode::V),
+        (ControlRole::ShoulderLeft, VirtualKeyCode::Q),
+        (ControlRole::ShoulderRight, VirtualKeyCode::W),
+    ],
+    &[
+        (ControlRole::Up, VirtualKeyCode::I),
+        (ControlRole::Down, VirtualKeyCode::K),
+        (ControlRole::Left, VirtualKeyCode::J),
+        (ControlRole::Right, VirtualKeyCode::L),
+        (ControlRole::Start, VirtualKeyCode::O),
+        (ControlRole::Select, VirtualKeyCode::U),
+        (ControlRole::FaceSouth, VirtualKeyCode::N),
+        (ControlRole::FaceEast, VirtualKeyCode::M),
+        (ControlRole::FaceWest, VirtualKeyCode::Comma),
+        (ControlRole::FaceNorth, VirtualKeyCode::Period),
+        (ControlRole::ShoulderLeft, VirtualKeyCode::Y),
+        (ControlRole::ShoulderRight, VirtualKeyCode::P),
+    ],
+];
+
+/// Bind keys to the buttons of the gamepads declared by the core, as a port and a button mask
+pub fn default_bindings(ports: &[Device]) -> HashMap<VirtualKeyCode, (u32, u64)> {
+    let mut bindings = HashMap::new();
+
+    let gamepads = ports.iter().enumerate().filter(|(_, device)| {
+        matches!(device.device_type, DeviceType::Joypad | DeviceType::Analog)
+    });
+
+    for ((port, device), keys) in gamepads.zip(DEFAULT_KEYS) {
+        for button in &device.buttons {
+            let key = keys.iter().find(|(role, _)| Some(*role) == button.role);
+
+            if let (Some((_, key)), Some(mask)) = (key, 1u64.checked_shl(button.id)) {
+                bindings.insert(*key, (port as u32, mask));
+            }
+        }
+    }
+
+    bindings
+}
+
+/// Maps a key to its USB HID usage ID, as expected by keyboard devices
+pub fn virtual_keycode_to_hid(keycode: &VirtualKeyCode) -> Option<u16> {
+    use VirtualKeyCode::*;
+
+    let usage = match keycode {
+        A => 0x04,
+        B => 0x05,
+        C => 0x06,
+        D => 0x07,
+        E => 0x08,
+        F => 0x09,
+        G => 0x0A,
+        H => 0x0B,
+        I => 0x0C,
+        J => 0x0D,
+        K => 0x0E,
+        L => 0x0F,
+        M => 0x10,
+        N => 0x11,
+        O => 0x12,
+        P => 0x13,
+        Q => 0x14,
+        R => 0x15,
+        S => 0x16,
+        T => 0x17,
+        U => 0x18,
+        V => 0x19,
+        W => 0x1A,
+        X => 0x1B,
+        Y => 0x1C,
+        Z => 0x1D,
+
+        Key1 => 0x1E,
+        Key2 => 0x1F,
+        Key3 => 0x20,
+        Key4 => 0x21,
+        Key5 => 0x22,
+        Key6 => 0x23,
+        Key7 => 0x24,
+        Key8 => 0x25,
+        Key9 => 0x26,
+        Key0 => 0x27,
+
+        Return => 0x28,
+        Escape => 0x29,
+        Back => 0x2A,
+        Tab => 0x2B,
+        Space => 0x2C,
+        Minus => 0x2D,
+        Equals => 0x2E,
+        LBracket => 0x2F,
+        RBracket => 0x30,
+        Backslash => 0x31,
+        Semicolon => 0x33,
+        Apostrophe => 0x34,
+        Grave => 0x35,
+        Comma => 0x36,
+        Period => 0x37,
+        Slash => 0x38,
+        Capital => 0x39,
+
+        F1 => 0x3A,
+        F2 => 0x3B,
+        F3 => 0x3C,
+        F4 => 0x3D,
+        F5 => 0x3E,
+        F6 => 0x3F,
+        F7 => 0x40,
+        F8 => 0x41,
+        F9 => 0x42,
+        F10 => 0x43,
+        F11 => 0x44,
+        F12 => 0x45,
+
+        Insert => 0x49,
+        Home => 0x4A,
+        PageUp => 0x4B,
+        Delete => 0x4C,
+        End => 0x4D,
+        PageDown => 0x4E,
+        Right => 0x4F,
+        Left => 0x50,
+        Down => 0x51,
+        Up => 0x52,
+
+        LControl => 0xE0,
+        LShift => 0xE1,
+        LAlt => 0xE2,
+        RControl => 0xE4,
+        RShift => 0xE5,
+        RAlt => 0xE6,
+
+        _ => return None,
+    };
+
+    Some(usage)
+}
@@ -2,6 +2,7 @@ use emulation_thread::EmulationMessage;
 use futures::executor::block_on;
 use wgpu::util::DeviceExt;
 
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::{sync::mpsc::Sender, thread::JoinHandle};
 
@@ -14,13 +15,18 @@ use winit::{
 use std::path::PathBuf;
 use structopt::StructOpt;
 
-use rustretro_plugin::ControllerInput;
+use rustretro_plugin::{DeviceInput, DeviceType, KeyboardEvent, PointerPosition, ResetKind};
+use rustretro_runner_common::CoreRuntime;
+#[cfg(not(feature = "native-nestadia"))]
 use rustretro_wasmtime_runner::Runner;
 
 mod emulation_thread;
+mod keyboard;
 
 #[derive(Debug, StructOpt)]
 struct Opt {
+    // The core is linked in the frontend
+    #[cfg(not(feature = "native-nestadia"))]
     #[structopt(parse(from_os_str))]
     core: Option<PathBuf>,
 
@@ -59,7 +65,16 @@ impl Vertex {
 
 struct State {
     emulator_handle: Sender<EmulationMessage>,
-    controller1: ControllerInput,
+    // Keys are bound from the controls the core declared
+    bindings: HashMap<VirtualKeyCode, (u32, u64)>,
+    controllers: Vec<DeviceInput>,
+    modifiers: ModifiersState,
+
+    // The keyboard and the mouse drive the first matching device of the core, if it has one
+    keyboard_port: Option<u32>,
+    pointer_port: Option<u32>,
+    pointer: DeviceInput,
+    emulator_size: winit::dpi::PhysicalSize<u32>,
 
     thread_join_handles: Vec<JoinHandle<()>>,
 
@@ -77,7 +92,10 @@ struct State {
 
 impl State {
     /// Create a new state and initialize the rendering pipeline.
-    async fn new(window: &winit::window::Window, emulator: Runner) -> Self {
+    async fn new(
+        window: &winit::window::Window,
+        emulator: impl CoreRuntime + Send + 'static,
+    ) -> Self {
         let size = window.inner_size();
 
         // Used prefered graphic API
@@ -118,6 +136,19 @@ impl State {
         let emulator_width = emulator.get_metadata().width;
         let emulator_height = emulator.get_metadata().height;
 
+        let find_port = |device_type| {
+            emulator
+                .get_metadata()
+                .ports
+                .iter()
+                .position(|device| device.device_type == device_type)
+                .map(|port| port as u32)
+        };
+        let bindings = keyboard::default_bindings(&emulator.get_metadata().ports);
+        let controllers = vec![DeviceInput::default(); emulator.get_metadata().ports.len()];
+        let keyboard_port = find_port(DeviceType::Keyboard);
+        let pointer_port = find_port(DeviceType::Pointer);
+
         // Create the texture to show the emulator screen
         let texture_size = wgpu::Extent3d {
             width: emulator_width,
@@ -295,7 +326,14 @@ impl State {
         Self {
             emulator_handle,
             thread_join_handles,
-            controller1: Default::default(),
+            bindings,
+            controllers,
+            modifiers: Default::default(),
+
+            keyboard_port,
+            pointer_port,
+            pointer: Default::default(),
+            emulator_size: winit::dpi::PhysicalSize::new(emulator_width, emulator_height),
 
             surface,
             config,
@@ -323,19 +361,66 @@ impl State {
     /// This is where we handle controller inputs
     fn input(&mut self, event: &WindowEvent) -> bool {
         match event {
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = *modifiers;
+                true
+            }
+
+            // A core with a keyboard gets every key, except Escape to leave
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(key_code),
+                        ..
+                    },
+                ..
+            } if self.keyboard_port.is_some() && *key_code != VirtualKeyCode::Escape => {
+                if let (Some(port), Some(keycode)) = (
+                    self.keyboard_port,
+                    keyboard::virtual_keycode_to_hid(key_code),
+                ) {
+                    let event = KeyboardEvent {
+                        keycode,
+                        pressed: *state == ElementState::Pressed,
+                    };
+                    let _ = self
+                        .emulator_handle
+                        .send(EmulationMessage::Key(port, event));
+                }
+                true
+            }
+
             WindowEvent::KeyboardInput { input, .. } => match input {
+                // R resets the console, and Shift+R power cycles it
+                KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::R),
+                    ..
+                } => {
+                    let kind = if self.modifiers.shift() {
+                        ResetKind::Hard
+                    } else {
+                        ResetKind::Soft
+                    };
+
+                    let _ = self.emulator_handle.send(EmulationMessage::Reset(kind));
+                    true
+                }
+
                 // Handle controller inputs
                 KeyboardInput {
                     state: ElementState::Pressed,
                     virtual_keycode: Some(key_code),
                     ..
                 } => {
-                    if let Ok(f) = virtual_keycode_to_controller_input(key_code) {
-                        self.controller1.insert(f);
+                    if let Some(&(port, mask)) = self.bindings.get(key_code) {
+                        let controller = &mut self.controllers[port as usize];
+                        controller.buttons |= mask;
 
                         let _ = self
                             .emulator_handle
-                            .send(EmulationMessage::Input(self.controller1));
+                            .send(EmulationMessage::Input(port, controller.clone()));
                         true
                     } else {
                         false
@@ -347,12 +432,13 @@ impl State {
                     virtual_keycode: Some(key_code),
                     ..
                 } => {
-                    if let Ok(f) = virtual_keycode_to_controller_input(key_code) {
-                        self.controller1.remove(f);
+                    if let Some(&(port, mask)) = self.bindings.get(key_code) {
+                        let controller = &mut self.controllers[port as usize];
+                        controller.buttons &= !mask;
 
                         let _ = self
                             .emulator_handle
-                            .send(EmulationMessage::Input(self.controller1));
+                            .send(EmulationMessage::Input(port, controller.clone()));
                         true
                     } else {
                         false
@@ -360,10 +446,54 @@ impl State {
                 }
                 _ => false,
             },
+
+            // The screen is stretched over the whole window
+            WindowEvent::CursorMoved { position, .. } if self.pointer_port.is_some() => {
+                let x = position.x * self.emulator_size.width as f64 / self.size.width as f64;
+                let y = position.y * self.emulator_size.height as f64 / self.size.height as f64;
+
+                let on_screen = (0.0..self.emulator_size.width as f64).contains(&x)
+                    && (0.0..self.emulator_size.height as f64).contains(&y);
+
+                self.pointer.pointer = on_screen.then_some(PointerPosition {
+                    x: x as u32,
+                    y: y as u32,
+                });
+                self.send_pointer()
+            }
+
+            WindowEvent::CursorLeft { .. } if self.pointer_port.is_some() => {
+                self.pointer.pointer = None;
+                self.send_pointer()
+            }
+
+            // The left button is the trigger
+            WindowEvent::MouseInput {
+                state,
+                button: MouseButton::Left,
+                ..
+            } if self.pointer_port.is_some() => {
+                match state {
+                    ElementState::Pressed => self.pointer.buttons |= 1,
+                    ElementState::Released => self.pointer.buttons &= !1,
+                }
+                self.send_pointer()
+            }
+
             _ => false,
         }
     }
 
+    fn send_pointer(&mut self) -> bool {
+        if let Some(port) = self.pointer_port {
+            let _ = self
+                .emulator_handle
+                .send(EmulationMessage::Input(port, self.pointer.clone()));
+        }
+
+        true
+    }
+
     /// Render the screen
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
         let output = self.surface.get_current_texture()?;
@@ -437,6 +567,7 @@ fn main() {
         .unwrap();
 
     // Find core path
+    #[cfg(not(feature = "native-nestadia"))]
     let core_path = if let Some(p) = opt.core {
         p
     } else {
@@ -464,11 +595,32 @@ fn main() {
     // Read the ROM
     let rom = std::fs::read(rom_path).expect("Could not read the ROM file");
 
+    // Read the save data, if the game was already saved
+    let save_data = std::fs::read(&save_path).ok();
+
     // Read the core
+    #[cfg(not(feature = "native-nestadia"))]
     let core = std::fs::read(core_path).expect("Could not read the core file");
 
     // Create the emulator
-    let emulator = Runner::new(&core, &rom, 1000);
+    #[cfg(not(feature = "native-nestadia"))]
+    let mut emulator = Runner::new(&core, &rom, save_data.as_deref(), 1000)
+        .unwrap_or_else(|e| panic!("Could not create the emulator: {}", e));
+
+    #[cfg(feature = "native-nestadia")]
+    let mut emulator =
+        rustretro_native_runner::Runner::<nestadia_rustretro::NestadiaRustretro>::new(
+            &rom,
+            save_data.as_deref(),
+        )
+        .unwrap_or_else(|e| panic!("Could not create the emulator: {}", e));
+
+    // Write the save data next to the ROM when it changes
+    emulator.on_save_data(move |data| {
+        if let Err(e) = std::fs::write(&save_path, data) {
+            eprintln!("Could not write the save file: {}", e);
+        }
+    });
 
     window.set_title(&emulator.get_metadata().name);
 
@@ -516,18 +668,3 @@ fn main() {
         _ => {}
     });
 }
-
-// This maps the keyboard input to a controller input
-fn virtual_keycode_to_controller_input(keycode: &VirtualKeyCode) -> Result<ControllerInput, ()> {
-    match keycode {
-        VirtualKeyCode::X => Ok(ControllerInput::A),
-        VirtualKeyCode::Z => Ok(ControllerInput::B),
-        VirtualKeyCode::S => Ok(ControllerInput::START),
-        VirtualKeyCode::A => Ok(ControllerInput::SELECT),
-        VirtualKeyCode::Down => Ok(ControllerInput::DOWN),
-        VirtualKeyCode::Left => Ok(ControllerInput::LEFT),
-        VirtualKeyCode::Right => Ok(ControllerInput::RIGHT),
-        VirtualKeyCode::Up => Ok(ControllerInput::UP),
-        _ => Err(()),
-    }
-}